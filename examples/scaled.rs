@@ -8,7 +8,7 @@ fn main() {
         // Cursor only supports scaled at the moment
         .add_plugin(PixelCursorPlugin)
         .add_plugin(PixelLimPlugin::new(4, false))
-        /* If you would like to see limited sprites randomly uncomment this plugin. Be warned of flashing images! */
+        /* If you would like to see limited sprites flicker uncomment this plugin. Be warned of flashing images! */
         // .add_plugin(PixelLimPlugin::new(4, true))
         .add_startup_system(setup)
         .add_systems((rotate_sprite, movement))
@@ -47,6 +47,7 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
             ..Default::default()
         },
         Rotate,
+        PixelRotation::default(),
         PixelLayer::Foreground(1),
         PixelSprite,
     ));
@@ -108,9 +109,10 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
     ));
 }
 
-fn rotate_sprite(mut rotate_query: Query<&mut Transform, With<Rotate>>, time: Res<Time>) {
-    for mut transform in rotate_query.iter_mut() {
-        transform.rotate_z(1.5 * time.delta_seconds());
+// PixelRotation keeps the pixels crisp where rotating the transform would blur them
+fn rotate_sprite(mut rotate_query: Query<&mut PixelRotation, With<Rotate>>, time: Res<Time>) {
+    for mut rotation in rotate_query.iter_mut() {
+        rotation.angle += 1.5 * time.delta_seconds();
     }
 }
 
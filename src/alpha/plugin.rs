@@ -0,0 +1,69 @@
+use bevy::{asset::HandleId, prelude::*, transform::TransformSystem, utils::HashMap};
+
+use crate::palette::system::quantize_images;
+
+use super::system::{apply_dither_alpha, threshold_images};
+
+/// How an image's alpha is made 1 bit. Pixels with alpha below `threshold` become fully transparent and the rest
+/// fully opaque. With cleanup_edges edge pixels that were see through take the color of an opaque neighbor instead of
+/// keeping the dark halo left by premultiplied alpha, and transparent pixels are cleared to 0
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AlphaThreshold {
+    pub threshold: u8,
+    pub cleanup_edges: bool,
+}
+
+impl Default for AlphaThreshold {
+    fn default() -> Self {
+        Self {
+            threshold: 128,
+            cleanup_edges: false,
+        }
+    }
+}
+
+/// The images that get their alpha thresholded as soon as they are loaded. `global` applies to every rgba image that
+/// isn't listed in `images`
+#[derive(Resource, Debug, Default, Clone)]
+pub struct AlphaThresholds {
+    pub global: Option<AlphaThreshold>,
+    pub images: HashMap<Handle<Image>, AlphaThreshold>,
+}
+
+impl AlphaThresholds {
+    pub fn add(&mut self, image: Handle<Image>, threshold: AlphaThreshold) {
+        self.images.insert(image, threshold);
+    }
+}
+
+/// The dithered copies made for [`super::system::DitherAlpha`], keyed by the original image, the alpha level, where
+/// the pattern starts within its 4x4 tile and whether the sprite is flipped. The handles are weak so a copy goes away
+/// with the last sprite using it, its entry is dropped then too
+#[derive(Resource, Debug, Default, Clone)]
+pub struct DitherAlphaCache {
+    pub images: HashMap<(HandleId, u8, UVec2, BVec2), Handle<Image>>,
+}
+
+/// Thresholds image alpha at load time so imported art with soft edges stays crisp and within the palette checks.
+/// Set `global` to do it for every image, otherwise add images to [`AlphaThresholds`]. With [`crate::plugin::PixelPlugins`]
+/// `global` is its `alpha_threshold`
+#[derive(Default)]
+pub struct PixelAlphaPlugin {
+    pub global: Option<AlphaThreshold>,
+}
+
+impl Plugin for PixelAlphaPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(AlphaThresholds {
+            global: self.global,
+            images: HashMap::default(),
+        })
+        .init_resource::<DitherAlphaCache>()
+        .add_system(threshold_images.before(quantize_images))
+        .add_system(
+            apply_dither_alpha
+                .in_base_set(CoreSet::PostUpdate)
+                .after(TransformSystem::TransformPropagate),
+        );
+    }
+}
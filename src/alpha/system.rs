@@ -0,0 +1,552 @@
+use bevy::{asset::HandleId, prelude::*, render::render_resource::TextureUsages, utils::HashMap};
+
+use crate::limit::system::sprite_rect;
+use crate::palette::plugin::QuantizedImages;
+use crate::palette::quantize::bayer_threshold;
+use crate::palette::system::{own_write, rgba_pixels, set_rgba_pixels};
+
+use super::plugin::{AlphaThreshold, AlphaThresholds, DitherAlphaCache};
+
+/// The width and height of the screen door pattern
+pub const DITHER_ALPHA_SIZE: i32 = 4;
+
+/// How many alpha levels the 4x4 screen door pattern has
+pub const DITHER_ALPHA_LEVELS: u8 = 16;
+
+/// Draws a sprite see through by leaving out pixels in an ordered dither pattern instead of blending, so it stays inside
+/// the palette. 0 is invisible and 1 is fully drawn. The pattern is lined up with the virtual pixel grid of the world so
+/// it doesn't crawl when the camera scrolls. Rotation and scaling are ignored and it can't be combined with a
+/// [`crate::palette::swap::PaletteSwap`]
+#[derive(Component, Debug, Copy, Clone, PartialEq)]
+pub struct DitherAlpha(pub f32);
+
+impl DitherAlpha {
+    /// The alpha rounded to a level of the pattern
+    pub fn level(&self) -> u8 {
+        (self.0.clamp(0.0, 1.0) * DITHER_ALPHA_LEVELS as f32).round() as u8
+    }
+}
+
+/// The image a dithered sprite had and the copy it was given, so it can be put back once the component is removed
+#[derive(Component, Debug, Clone)]
+pub struct DitherAlphaSource {
+    pub original: Handle<Image>,
+    pub dithered: Handle<Image>,
+}
+
+/// Makes a copy of the image with pixels left out in a 4x4 bayer pattern. `origin` is the grid position of the first
+/// pixel and the grid is walked backwards on flipped axes. Returns None if the image isn't 8 bit rgba
+pub fn dither_alpha_image(image: &Image, level: u8, origin: IVec2, flip: BVec2) -> Option<Image> {
+    let mut pixels = rgba_pixels(image)?;
+    let width = image.texture_descriptor.size.width as i32;
+    for (index, pixel) in pixels.iter_mut().enumerate() {
+        let (u, v) = (index as i32 % width, index as i32 / width);
+        let x = if flip.x { origin.x - u } else { origin.x + u };
+        let y = if flip.y { origin.y - v } else { origin.y + v };
+        let threshold = bayer_threshold(
+            DITHER_ALPHA_SIZE as u32,
+            x.rem_euclid(DITHER_ALPHA_SIZE) as u32,
+            y.rem_euclid(DITHER_ALPHA_SIZE) as u32,
+        );
+        if threshold * DITHER_ALPHA_LEVELS as f32 >= level as f32 {
+            pixel[3] = 0;
+        }
+    }
+    let mut dithered = image.clone();
+    set_rgba_pixels(&mut dithered, &pixels).then_some(dithered)
+}
+
+/// Where the first pixel of a sprite's texture lands on the world pixel grid. The grid counts rows downwards like the texture does
+fn grid_origin(transform: &GlobalTransform, sprite: &Sprite, image_size: Vec2) -> IVec2 {
+    let rect = sprite_rect(transform, sprite, Some(image_size));
+    let source = sprite.rect.map_or(Vec2::ZERO, |rect| rect.min).as_ivec2();
+    let x = match sprite.flip_x {
+        false => rect.min.x.round() as i32 - source.x,
+        true => rect.max.x.round() as i32 - 1 + source.x,
+    };
+    let y = match sprite.flip_y {
+        false => -(rect.max.y.round() as i32) - source.y,
+        true => -(rect.min.y.round() as i32) - 1 + source.y,
+    };
+    IVec2::new(x, y)
+}
+
+/// Makes the alpha of a buffer of pixels `width` pixels wide 1 bit. Returns whether any pixel changed. Running it
+/// again on its own output changes nothing
+pub fn threshold_pixels(pixels: &mut [[u8; 4]], width: usize, settings: AlphaThreshold) -> bool {
+    if width == 0 {
+        return false;
+    }
+    let original = pixels.to_vec();
+    let height = pixels.len() / width;
+    for y in 0..height {
+        for x in 0..width {
+            let index = y * width + x;
+            let [r, g, b, a] = original[index];
+            pixels[index] = if a < settings.threshold {
+                match settings.cleanup_edges {
+                    true => [0, 0, 0, 0],
+                    false => [r, g, b, 0],
+                }
+            } else if a < u8::MAX && settings.cleanup_edges {
+                let [r, g, b] =
+                    opaque_neighbor(&original, width, height, x, y).unwrap_or([r, g, b]);
+                [r, g, b, u8::MAX]
+            } else {
+                [r, g, b, u8::MAX]
+            };
+        }
+    }
+    pixels != original.as_slice()
+}
+
+/// The color of the first fully opaque pixel around a pixel
+fn opaque_neighbor(
+    pixels: &[[u8; 4]],
+    width: usize,
+    height: usize,
+    x: usize,
+    y: usize,
+) -> Option<[u8; 3]> {
+    (-1..=1)
+        .flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+        .filter(|offset| *offset != (0, 0))
+        .filter_map(|(dx, dy)| {
+            let x = x.checked_add_signed(dx).filter(|x| *x < width)?;
+            let y = y.checked_add_signed(dy).filter(|y| *y < height)?;
+            let [r, g, b, a] = pixels[y * width + x];
+            (a == u8::MAX).then_some([r, g, b])
+        })
+        .next()
+}
+
+/// Thresholds an image in place. Returns false if the image isn't 8 bit rgba or nothing changed
+pub fn threshold_image(image: &mut Image, settings: AlphaThreshold) -> bool {
+    let Some(mut pixels) = rgba_pixels(image) else {
+        return false;
+    };
+    let width = image.texture_descriptor.size.width as usize;
+    threshold_pixels(&mut pixels, width, settings) && set_rgba_pixels(image, &pixels)
+}
+
+/// Thresholds images from [`AlphaThresholds`] when they are loaded or changed, or when the thresholds are changed. Render targets are left alone and
+/// images are only written to when something actually changed, so other image passes don't keep setting each other off.
+/// Images in [`QuantizedImages`] are thresholded by [`crate::palette::system::quantize_images`] instead
+pub fn threshold_images(
+    thresholds: Res<AlphaThresholds>,
+    quantized: Option<Res<QuantizedImages>>,
+    mut images: ResMut<Assets<Image>>,
+    mut image_events: EventReader<AssetEvent<Image>>,
+    mut written: Local<HashMap<HandleId, u32>>,
+) {
+    let mut pending = Vec::new();
+    for event in image_events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                if !own_write(&mut written, handle.id()) {
+                    pending.push(handle.clone_weak());
+                }
+            }
+            AssetEvent::Removed { handle } => {
+                written.remove(&handle.id());
+            }
+        }
+    }
+    if thresholds.is_changed() {
+        pending.extend(thresholds.images.keys().map(Handle::clone_weak));
+        // A global threshold set at runtime also covers the images that are already loaded
+        if thresholds.global.is_some() {
+            pending.extend(images.ids().map(Handle::weak));
+        }
+    }
+    for handle in pending {
+        if quantized
+            .as_ref()
+            .is_some_and(|quantized| quantized.images.contains_key(&handle))
+        {
+            continue;
+        }
+        let Some(settings) = thresholds
+            .images
+            .get(&handle)
+            .copied()
+            .or(thresholds.global)
+        else {
+            continue;
+        };
+        let Some(image) = images.get(&handle) else {
+            continue;
+        };
+        if image
+            .texture_descriptor
+            .usage
+            .contains(TextureUsages::RENDER_ATTACHMENT)
+        {
+            continue;
+        }
+        let Some(mut pixels) = rgba_pixels(image) else {
+            continue;
+        };
+        let width = image.texture_descriptor.size.width as usize;
+        // Only borrow the image mutably when it changes since that alone counts as a modification
+        if threshold_pixels(&mut pixels, width, settings) {
+            if let Some(image) = images.get_mut(&handle) {
+                set_rgba_pixels(image, &pixels);
+                *written.entry(handle.id()).or_default() += 1;
+            }
+        }
+    }
+}
+
+/// Gives sprites with a [`DitherAlpha`] the dithered copy of their image matching their level and spot on the pixel
+/// grid, making copies as needed. Copies are remade when the original image changes and sprites get their image back
+/// once the component is removed. The cache only holds weak handles so copies no sprite uses anymore are freed
+#[allow(clippy::type_complexity)]
+pub fn apply_dither_alpha(
+    mut commands: Commands,
+    mut dither_query: Query<(
+        Entity,
+        &DitherAlpha,
+        &Sprite,
+        &GlobalTransform,
+        &mut Handle<Image>,
+        Option<&mut DitherAlphaSource>,
+    )>,
+    mut restore_query: Query<(&DitherAlphaSource, &mut Handle<Image>), Without<DitherAlpha>>,
+    mut removed: RemovedComponents<DitherAlpha>,
+    mut cache: ResMut<DitherAlphaCache>,
+    mut images: ResMut<Assets<Image>>,
+    mut image_events: EventReader<AssetEvent<Image>>,
+) {
+    for entity in removed.iter() {
+        if let Ok((source, mut image)) = restore_query.get_mut(entity) {
+            *image = source.original.clone();
+            commands.entity(entity).remove::<DitherAlphaSource>();
+        }
+    }
+    for event in image_events.iter() {
+        match event {
+            AssetEvent::Modified { handle } => {
+                cache
+                    .images
+                    .retain(|(original, ..), _| *original != handle.id());
+            }
+            AssetEvent::Removed { handle } => {
+                cache.images.retain(|(original, ..), dithered| {
+                    *original != handle.id() && dithered.id() != handle.id()
+                });
+            }
+            AssetEvent::Created { .. } => (),
+        }
+    }
+
+    for (entity, dither, sprite, transform, mut image, source) in dither_query.iter_mut() {
+        let original = match source.as_deref() {
+            Some(source) if *image == source.dithered => source.original.clone(),
+            _ => image.clone(),
+        };
+        let level = dither.level();
+        let dithered = if level >= DITHER_ALPHA_LEVELS {
+            original.clone()
+        } else {
+            let Some(size) = images.get(&original).map(|image| image.size()) else {
+                continue;
+            };
+            // Only the spot within the pattern matters so sprites moving around reuse the same few copies
+            let origin = grid_origin(transform, sprite, size);
+            let origin = IVec2::new(
+                origin.x.rem_euclid(DITHER_ALPHA_SIZE),
+                origin.y.rem_euclid(DITHER_ALPHA_SIZE),
+            );
+            let flip = BVec2::new(sprite.flip_x, sprite.flip_y);
+            let key = (original.id(), level, origin.as_uvec2(), flip);
+            let cached = cache
+                .images
+                .get(&key)
+                .filter(|dithered| images.contains(*dithered));
+            // The cache only has weak handles, the sprite needs a strong one to keep the image alive
+            match cached {
+                Some(dithered) => images.get_handle(dithered),
+                None => {
+                    let Some(dithered) = images
+                        .get(&original)
+                        .and_then(|image| dither_alpha_image(image, level, origin, flip))
+                    else {
+                        warn!(
+                            "Image {:?} can't be dithered since it isn't 8 bit rgba",
+                            original.id()
+                        );
+                        continue;
+                    };
+                    let dithered = images.add(dithered);
+                    cache.images.insert(key, dithered.clone_weak());
+                    dithered
+                }
+            }
+        };
+        if *image != dithered {
+            *image = dithered.clone();
+        }
+        match source {
+            Some(mut source) => {
+                if source.original != original || source.dithered != dithered {
+                    *source = DitherAlphaSource { original, dithered };
+                }
+            }
+            None => {
+                commands
+                    .entity(entity)
+                    .insert(DitherAlphaSource { original, dithered });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+    use super::*;
+
+    const HARD: AlphaThreshold = AlphaThreshold {
+        threshold: 128,
+        cleanup_edges: false,
+    };
+
+    const CLEAN: AlphaThreshold = AlphaThreshold {
+        threshold: 128,
+        cleanup_edges: true,
+    };
+
+    #[test]
+    fn alpha_is_made_one_bit() {
+        let mut pixels = [
+            [10, 20, 30, 0],
+            [10, 20, 30, 127],
+            [10, 20, 30, 128],
+            [10, 20, 30, 255],
+        ];
+        assert!(threshold_pixels(&mut pixels, 4, HARD));
+        assert_eq!(
+            pixels,
+            [
+                [10, 20, 30, 0],
+                [10, 20, 30, 0],
+                [10, 20, 30, 255],
+                [10, 20, 30, 255]
+            ]
+        );
+    }
+
+    #[test]
+    fn edges_take_the_color_of_an_opaque_neighbor() {
+        // A dark halo pixel next to a red one and a faint pixel next to nothing opaque
+        let mut pixels = [
+            [200, 0, 0, 255],
+            [40, 0, 0, 200],
+            [0, 0, 0, 0],
+            [9, 9, 9, 50],
+        ];
+        threshold_pixels(&mut pixels, 2, CLEAN);
+        assert_eq!(
+            pixels,
+            [
+                [200, 0, 0, 255],
+                [200, 0, 0, 255],
+                [0, 0, 0, 0],
+                [0, 0, 0, 0]
+            ]
+        );
+
+        // Without an opaque neighbor the pixel keeps its own color
+        let mut pixels = [[40, 0, 0, 200]];
+        threshold_pixels(&mut pixels, 1, CLEAN);
+        assert_eq!(pixels, [[40, 0, 0, 255]]);
+    }
+
+    #[test]
+    fn thresholding_twice_changes_nothing() {
+        for settings in [HARD, CLEAN] {
+            let mut pixels = [
+                [1, 2, 3, 90],
+                [200, 100, 0, 255],
+                [50, 60, 70, 180],
+                [0, 0, 0, 0],
+            ];
+            assert!(threshold_pixels(&mut pixels, 2, settings));
+            let once = pixels;
+            assert!(!threshold_pixels(&mut pixels, 2, settings));
+            assert_eq!(pixels, once);
+        }
+    }
+
+    #[test]
+    fn empty_buffers_are_left_alone() {
+        assert!(!threshold_pixels(&mut [], 0, HARD));
+        assert!(!threshold_pixels(&mut [[0, 0, 0, 255]], 0, HARD));
+    }
+
+    #[test]
+    fn global_threshold_set_at_runtime_covers_loaded_images() {
+        let mut app = App::new();
+        app.add_plugin(TaskPoolPlugin::default())
+            .add_plugin(AssetPlugin::default())
+            .add_asset::<Image>()
+            .init_resource::<AlphaThresholds>()
+            .add_system(threshold_images);
+        let image = app
+            .world
+            .resource_mut::<Assets<Image>>()
+            .add(Image::new_fill(
+                Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+                TextureDimension::D2,
+                &[10, 20, 30, 200],
+                TextureFormat::Rgba8UnormSrgb,
+            ));
+        app.update();
+        app.update();
+        let alpha = |app: &App| {
+            app.world
+                .resource::<Assets<Image>>()
+                .get(&image)
+                .unwrap()
+                .data[3]
+        };
+        assert_eq!(alpha(&app), 200);
+        app.world.resource_mut::<AlphaThresholds>().global = Some(HARD);
+        app.update();
+        assert_eq!(alpha(&app), 255);
+    }
+
+    fn opaque_image(width: u32, height: u32) -> Image {
+        Image::new_fill(
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[255; 4],
+            TextureFormat::Rgba8UnormSrgb,
+        )
+    }
+
+    fn alpha_mask(image: &Image) -> Vec<bool> {
+        image.data.chunks(4).map(|pixel| pixel[3] != 0).collect()
+    }
+
+    #[test]
+    fn dither_levels_keep_that_many_pixels_of_each_tile() {
+        let image = opaque_image(4, 4);
+        for level in [0, 1, 8, 15] {
+            let dithered = dither_alpha_image(&image, level, IVec2::ZERO, BVec2::FALSE).unwrap();
+            let opaque = alpha_mask(&dithered)
+                .into_iter()
+                .filter(|opaque| *opaque)
+                .count();
+            assert_eq!(opaque, level as usize);
+        }
+        // Each level keeps the pixels of the one below it so fades don't shimmer
+        let half = alpha_mask(&dither_alpha_image(&image, 8, IVec2::ZERO, BVec2::FALSE).unwrap());
+        let more = alpha_mask(&dither_alpha_image(&image, 9, IVec2::ZERO, BVec2::FALSE).unwrap());
+        assert!(half.iter().zip(&more).all(|(half, more)| !half || *more));
+    }
+
+    #[test]
+    fn dither_pattern_follows_the_origin() {
+        let image = opaque_image(8, 4);
+        let at = |origin, flip| alpha_mask(&dither_alpha_image(&image, 5, origin, flip).unwrap());
+        let base = at(IVec2::ZERO, BVec2::FALSE);
+        let shifted = at(IVec2::new(1, 0), BVec2::FALSE);
+        for y in 0..4 {
+            for x in 0..7 {
+                assert_eq!(shifted[y * 8 + x], base[y * 8 + x + 1]);
+            }
+        }
+        // Whole tiles apart is the same pattern
+        assert_eq!(at(IVec2::new(4, -8), BVec2::FALSE), base);
+        // Flipped sprites walk the grid backwards from their right edge
+        let flipped = at(IVec2::new(7, 0), BVec2::new(true, false));
+        for y in 0..4 {
+            for x in 0..8 {
+                assert_eq!(flipped[y * 8 + x], base[y * 8 + 7 - x]);
+            }
+        }
+    }
+
+    #[test]
+    fn grid_origin_is_the_first_texture_pixel() {
+        let transform = GlobalTransform::from_xyz(2.0, 2.0, 0.0);
+        let size = Vec2::splat(4.0);
+        let sprite = Sprite::default();
+        assert_eq!(grid_origin(&transform, &sprite, size), IVec2::new(0, -4));
+        let flipped = Sprite {
+            flip_x: true,
+            flip_y: true,
+            ..default()
+        };
+        assert_eq!(grid_origin(&transform, &flipped, size), IVec2::new(3, -1));
+        // A rect moves the texture so its own first pixel lines up
+        let rect = Sprite {
+            rect: Some(Rect::new(1.0, 1.0, 3.0, 3.0)),
+            ..default()
+        };
+        assert_eq!(grid_origin(&transform, &rect, size), IVec2::new(0, -4));
+    }
+
+    fn dither_app() -> App {
+        let mut app = App::new();
+        app.add_plugin(TaskPoolPlugin::default())
+            .add_plugin(AssetPlugin::default())
+            .add_asset::<Image>()
+            .init_resource::<DitherAlphaCache>()
+            .add_system(apply_dither_alpha);
+        app
+    }
+
+    #[test]
+    fn dithered_copies_are_shared_and_freed() {
+        let mut app = dither_app();
+        let original = app
+            .world
+            .resource_mut::<Assets<Image>>()
+            .add(opaque_image(4, 4));
+        // A whole tile apart so both land on the same spot of the pattern
+        let sprites = [0.0, 4.0].map(|x| {
+            app.world
+                .spawn((
+                    DitherAlpha(0.5),
+                    Sprite::default(),
+                    GlobalTransform::from_xyz(x, 0.0, 0.0),
+                    original.clone(),
+                ))
+                .id()
+        });
+        app.update();
+        let images = sprites.map(|sprite| app.world.get::<Handle<Image>>(sprite).unwrap().clone());
+        assert_eq!(images[0], images[1]);
+        assert_ne!(images[0], original);
+        assert_eq!(app.world.resource::<DitherAlphaCache>().images.len(), 1);
+
+        app.world.entity_mut(sprites[0]).remove::<DitherAlpha>();
+        app.update();
+        assert_eq!(app.world.get::<Handle<Image>>(sprites[0]), Some(&original));
+        assert!(app.world.get::<DitherAlphaSource>(sprites[0]).is_none());
+
+        let dithered = images[0].id();
+        drop(images);
+        app.world.despawn(sprites[1]);
+        for _ in 0..6 {
+            app.update();
+        }
+        assert!(!app
+            .world
+            .resource::<Assets<Image>>()
+            .contains(&Handle::weak(dithered)));
+        assert!(app.world.resource::<DitherAlphaCache>().images.is_empty());
+    }
+}
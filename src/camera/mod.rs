@@ -1,3 +1,4 @@
 pub mod plugin;
 pub mod scaled;
 pub mod texture;
+pub mod upscale;
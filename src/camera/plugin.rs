@@ -1,13 +1,25 @@
 use bevy::{
+    asset::load_internal_asset,
     prelude::*,
     render::{
-        camera::{self, CameraUpdateSystem, ScalingMode},
+        camera::{self, ScalingMode},
         primitives::Aabb,
-        view::VisibleEntities,
+        view::{RenderLayers, VisibleEntities},
     },
+    sprite::Material2dPlugin,
 };
 
-use super::scaled::ScaledPixelProjection;
+use super::{
+    scaled::ScaledPixelProjection,
+    upscale::{UpscaleMaterial, UPSCALE_SHADER_HANDLE},
+};
+
+/// The render layer the texture camera's canvas is drawn to the window on
+pub const CANVAS_RENDER_LAYER: u8 = (RenderLayers::TOTAL_LAYERS - 1) as u8;
+/// The render layer of the full resolution camera drawn on top of the pixel camera. Useful for huds that shouldn't be pixelated
+pub const UI_RENDER_LAYER: u8 = (RenderLayers::TOTAL_LAYERS - 2) as u8;
+/// The render layer the pixel cursor is drawn on
+pub const CURSOR_RENDER_LAYER: u8 = (RenderLayers::TOTAL_LAYERS - 3) as u8;
 
 #[derive(Component)]
 pub struct PixelCameraTag;
@@ -22,7 +34,14 @@ pub struct PixelCameraPlugin;
 
 impl Plugin for PixelCameraPlugin {
     fn build(&self, app: &mut App) {
-        app.register_type::<Camera>()
+        load_internal_asset!(
+            app,
+            UPSCALE_SHADER_HANDLE,
+            "upscale.wgsl",
+            Shader::from_wgsl
+        );
+        app.add_plugin(Material2dPlugin::<UpscaleMaterial>::default())
+            .register_type::<Camera>()
             .register_type::<Visibility>()
             .register_type::<ComputedVisibility>()
             .register_type::<OrthographicProjection>()
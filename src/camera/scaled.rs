@@ -7,7 +7,7 @@ use bevy::window::PrimaryWindow;
 
 use crate::prelude::PixelCameraTag;
 
-use super::plugin::{CursorCameraTag, UiCameraTag};
+use super::plugin::{CursorCameraTag, UiCameraTag, CURSOR_RENDER_LAYER, UI_RENDER_LAYER};
 
 /// This is a camera that scaled up pixels and aligns them to a virtual grid. This is tooken from bevy_pixel_camera
 /// The advantage of this camera is smoother scrolling, rotation, etc
@@ -149,6 +149,9 @@ pub struct ScaledPixelProjection {
     /// If true pixels don't have to be an integer value and can be instead a float
     pub imperfect: bool,
 
+    /// The width of a virtual pixel divided by its height. Some consoles had pixels that weren't square, ie 8/7 for the NES
+    pub pixel_aspect: f32,
+
     pub hdr: bool,
 
     pub init: bool,
@@ -172,7 +175,7 @@ impl CameraProjection for ScaledPixelProjection {
         let mut zoom_x = None;
         if let Some(desired_width) = self.desired_width {
             if desired_width > 0 {
-                zoom_x = Some(width / desired_width as f32);
+                zoom_x = Some(width / (desired_width as f32 * self.pixel_aspect));
             }
         }
         let mut zoom_y = None;
@@ -191,7 +194,7 @@ impl CameraProjection for ScaledPixelProjection {
             self.zoom = self.zoom.round();
         }
 
-        let actual_width = width / (self.zoom);
+        let actual_width = width / (self.zoom * self.pixel_aspect);
         let actual_height = height / (self.zoom);
         if self.centered {
             self.left = -((actual_width as i32) / 2) as f32;
@@ -225,6 +228,7 @@ impl Default for ScaledPixelProjection {
             zoom: 1.0,
             centered: true,
             imperfect: false,
+            pixel_aspect: 1.0,
             init: false,
             hdr: true,
         }
@@ -238,7 +242,7 @@ pub fn setup_camera(
     if let Ok((mut projection, _entity)) = camera.get_single_mut() {
         if !projection.init {
             projection.init = true;
-            let ui_layer = RenderLayers::layer((RenderLayers::TOTAL_LAYERS - 2) as u8);
+            let ui_layer = RenderLayers::layer(UI_RENDER_LAYER);
             commands.spawn((
                 Camera2dBundle {
                     camera: Camera {
@@ -255,7 +259,7 @@ pub fn setup_camera(
                 UiCameraTag,
                 ui_layer,
             ));
-            let cursor_layer = RenderLayers::layer((RenderLayers::TOTAL_LAYERS - 3) as u8);
+            let cursor_layer = RenderLayers::layer(CURSOR_RENDER_LAYER);
             commands.spawn((
                 Camera2dBundle {
                     camera: Camera {
@@ -284,7 +288,7 @@ pub fn update_scaled_viewport(
         for (mut camera, projection) in camera_query.iter_mut() {
             let screen_width = projection.desired_width.map(|w| w as f32).unwrap_or(0.0);
             let screen_height = projection.desired_height.map(|h| h as f32).unwrap_or(0.0);
-            let aspect_ratio = screen_width / screen_height;
+            let aspect_ratio = screen_width * projection.pixel_aspect / screen_height;
             let window_size: UVec2 = if window.physical_height() > window.physical_width()
                 || window.physical_height() as f32 * aspect_ratio > window.physical_width() as f32
             {
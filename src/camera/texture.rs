@@ -10,18 +10,27 @@ use bevy::{prelude::*, sprite::MaterialMesh2dBundle};
 
 use crate::prelude::PixelCameraTag;
 
-use super::plugin::{CursorCameraTag, UiCameraTag};
+use super::{
+    plugin::{
+        CursorCameraTag, UiCameraTag, CANVAS_RENDER_LAYER, CURSOR_RENDER_LAYER, UI_RENDER_LAYER,
+    },
+    upscale::{UpscaleFilter, UpscaleMaterial, UpscaleSettings},
+};
 
 /// This is for cameras that you want things to render to a texture then be scaled.
 /// size is the size of the virtual canvas and fixed is whether or not to let it grow in a certain direction.
 /// Ie a fixed height camera but is allowed to scale horizontally would go like fixed_axis: Some(false). the bool is for which axis. false being its fixed vertically true being fixed horizontally
 /// The advantage of this camera is anything you draw will be pixelized including 3d assets. And one may see the retro look of less smooth scrolling more appealing.
+/// pixel_aspect is the width of a virtual pixel divided by its height for consoles that didn't have square pixels.
+/// filter is how the canvas is scaled up to the window, it can be changed at any time.
 #[derive(Component)]
 pub struct TexturePixelCamera {
     pub size: UVec2,
     pub fixed_axis: Option<bool>,
     pub clear_color: Color,
     pub hdr: bool,
+    pub pixel_aspect: f32,
+    pub filter: UpscaleFilter,
     init: bool,
 }
 
@@ -37,6 +46,8 @@ impl Default for TexturePixelCamera {
             size: UVec2::new(256, 224),
             fixed_axis: None,
             clear_color: Color::WHITE,
+            pixel_aspect: 1.0,
+            filter: UpscaleFilter::Nearest,
             init: false,
             hdr: false,
         }
@@ -49,6 +60,8 @@ impl TexturePixelCamera {
             size,
             fixed_axis: axis,
             clear_color,
+            pixel_aspect: 1.0,
+            filter: UpscaleFilter::Nearest,
             init: false,
             hdr,
         }
@@ -59,6 +72,8 @@ impl TexturePixelCamera {
             size: UVec2::new(0, height),
             fixed_axis: Some(false),
             clear_color: Color::WHITE,
+            pixel_aspect: 1.0,
+            filter: UpscaleFilter::Nearest,
             init: false,
             hdr: false,
         }
@@ -68,6 +83,8 @@ impl TexturePixelCamera {
             size: UVec2::new(width, 0),
             fixed_axis: Some(true),
             clear_color: Color::WHITE,
+            pixel_aspect: 1.0,
+            filter: UpscaleFilter::Nearest,
             init: false,
             hdr: false,
         }
@@ -77,6 +94,8 @@ impl TexturePixelCamera {
             size: UVec2::new(width, height),
             fixed_axis: None,
             clear_color: Color::WHITE,
+            pixel_aspect: 1.0,
+            filter: UpscaleFilter::Nearest,
             init: false,
             hdr: false,
         }
@@ -87,7 +106,7 @@ pub fn setup_camera(
     mut commands: Commands,
     mut camera: Query<(&mut TexturePixelCamera, Entity)>,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut materials: ResMut<Assets<UpscaleMaterial>>,
     mut images: ResMut<Assets<Image>>,
 ) {
     for (mut pixel_camera, entity) in camera.iter_mut() {
@@ -157,8 +176,8 @@ pub fn setup_camera(
                 .entity(entity)
                 .insert((PixelCameraTag, UiCameraConfig { show_ui: false }));
 
-            let render_layer = RenderLayers::layer((RenderLayers::TOTAL_LAYERS - 1) as u8);
-            let ui_layer = RenderLayers::layer((RenderLayers::TOTAL_LAYERS - 2) as u8);
+            let render_layer = RenderLayers::layer(CANVAS_RENDER_LAYER);
+            let ui_layer = RenderLayers::layer(UI_RENDER_LAYER);
 
             let quad_handle = meshes.add(Mesh::from(shape::Quad::new(Vec2::new(
                 size.width as f32,
@@ -169,9 +188,13 @@ pub fn setup_camera(
             commands.spawn((
                 MaterialMesh2dBundle {
                     mesh: quad_handle.into(),
-                    material: materials.add(ColorMaterial {
-                        texture: Some(image_handle),
-                        ..Default::default()
+                    material: materials.add(UpscaleMaterial {
+                        settings: UpscaleSettings {
+                            filter: pixel_camera.filter.id(),
+                            scale: Vec2::ONE,
+                        }
+                        .into(),
+                        texture: image_handle,
                     }),
                     transform: Transform { ..default() },
                     ..default()
@@ -216,7 +239,7 @@ pub fn setup_camera(
                 ui_layer,
             ));
 
-            let cursor_layer = RenderLayers::layer((RenderLayers::TOTAL_LAYERS - 3) as u8);
+            let cursor_layer = RenderLayers::layer(CURSOR_RENDER_LAYER);
             commands.spawn((
                 Camera2dBundle {
                     camera: Camera {
@@ -238,17 +261,19 @@ pub fn setup_camera(
 }
 
 pub fn scale_render_image(
-    mut texture_query: Query<&mut Transform, With<RenderImage>>,
+    mut texture_query: Query<(&mut Transform, &Handle<UpscaleMaterial>), With<RenderImage>>,
+    mut materials: ResMut<Assets<UpscaleMaterial>>,
     mut camera_query: Query<&mut bevy::render::camera::Camera, With<FinalCameraTag>>,
     mut pixel_camera_query: Query<&TexturePixelCamera, With<PixelCameraTag>>,
     mut windows: Query<&mut Window, With<PrimaryWindow>>,
 ) {
-    if let Ok(mut texture_transform) = texture_query.get_single_mut() {
+    if let Ok((mut texture_transform, material)) = texture_query.get_single_mut() {
         if let Ok(window) = windows.get_single_mut() {
             if let Ok(mut camera) = camera_query.get_single_mut() {
                 if let Ok(pixel_camera) = pixel_camera_query.get_single_mut() {
                     let (screen_width, screen_height) = (pixel_camera.size.x, pixel_camera.size.y);
-                    let aspect_ratio = screen_width as f32 / screen_height as f32;
+                    let aspect_ratio =
+                        screen_width as f32 * pixel_camera.pixel_aspect / screen_height as f32;
                     let window_size: UVec2 = if window.physical_height() > window.physical_width()
                         || window.physical_height() as f32 * aspect_ratio
                             > window.physical_width() as f32
@@ -287,6 +312,17 @@ pub fn scale_render_image(
                     };
 
                     texture_transform.scale = Vec3::new(scale_width, scale_height, 1.0);
+                    let settings = UpscaleSettings {
+                        filter: pixel_camera.filter.id(),
+                        scale: Vec2::new(scale_width, scale_height),
+                    }
+                    .into();
+                    // Only touch the material when it changes so it isn't prepared again every frame
+                    if materials.get(material).map(|material| material.settings) != Some(settings) {
+                        if let Some(material) = materials.get_mut(material) {
+                            material.settings = settings;
+                        }
+                    }
 
                     camera.viewport = Some(Viewport {
                         physical_size: window_size,
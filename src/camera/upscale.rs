@@ -0,0 +1,112 @@
+use bevy::{
+    prelude::*,
+    reflect::TypeUuid,
+    render::render_resource::{AsBindGroup, ShaderRef},
+    sprite::Material2d,
+};
+
+use crate::scale::{nearest, scale2x, scale3x, sharp_bilinear, xbr2x};
+
+pub const UPSCALE_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 8410348165920533019);
+
+/// How the canvas of a [`super::texture::TexturePixelCamera`] is scaled up to the window. Scale2x, Scale3x and xBR
+/// round off diagonal edges, sharp bilinear keeps pixels square but smooths the seams between them at uneven scales
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum UpscaleFilter {
+    #[default]
+    Nearest,
+    Scale2x,
+    Scale3x,
+    SharpBilinear,
+    Xbr,
+}
+
+impl UpscaleFilter {
+    /// The number the shader knows the filter by
+    pub fn id(&self) -> u32 {
+        match self {
+            UpscaleFilter::Nearest => 0,
+            UpscaleFilter::Scale2x => 1,
+            UpscaleFilter::Scale3x => 2,
+            UpscaleFilter::SharpBilinear => 3,
+            UpscaleFilter::Xbr => 4,
+        }
+    }
+
+    /// Cpu version of what the shader draws for a canvas of `width` by `height` 8 bit srgb pixels shown at
+    /// `out_width` by `out_height`. Handy for checking the filters without a gpu
+    pub fn apply(
+        &self,
+        pixels: &[[u8; 4]],
+        width: usize,
+        height: usize,
+        out_width: usize,
+        out_height: usize,
+    ) -> Vec<[u8; 4]> {
+        match self {
+            UpscaleFilter::Nearest => nearest(pixels, width, height, out_width, out_height),
+            UpscaleFilter::Scale2x => nearest(
+                &scale2x(pixels, width, height),
+                width * 2,
+                height * 2,
+                out_width,
+                out_height,
+            ),
+            UpscaleFilter::Scale3x => nearest(
+                &scale3x(pixels, width, height),
+                width * 3,
+                height * 3,
+                out_width,
+                out_height,
+            ),
+            UpscaleFilter::SharpBilinear => {
+                sharp_bilinear(pixels, width, height, out_width, out_height)
+            }
+            UpscaleFilter::Xbr => nearest(
+                &xbr2x(pixels, width, height),
+                width * 2,
+                height * 2,
+                out_width,
+                out_height,
+            ),
+        }
+    }
+}
+
+/// What the upscale shader needs besides the canvas
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct UpscaleSettings {
+    pub filter: u32,
+    /// How many window pixels each canvas pixel covers
+    pub scale: Vec2,
+}
+
+impl From<UpscaleSettings> for Vec4 {
+    /// Packs the settings into the single vec4 uniform the shader reads, filter in x and scale in yz
+    fn from(settings: UpscaleSettings) -> Self {
+        Vec4::new(
+            settings.filter as f32,
+            settings.scale.x,
+            settings.scale.y,
+            0.0,
+        )
+    }
+}
+
+/// Draws the canvas of a texture camera with an [`UpscaleFilter`]
+#[derive(AsBindGroup, TypeUuid, Debug, Clone)]
+#[uuid = "5ab3f0e4-5b7c-4a8e-9d3e-0c9a4b6e2f17"]
+pub struct UpscaleMaterial {
+    /// [`UpscaleSettings`] packed into a vec4
+    #[uniform(0)]
+    pub settings: Vec4,
+    #[texture(1)]
+    pub texture: Handle<Image>,
+}
+
+impl Material2d for UpscaleMaterial {
+    fn fragment_shader() -> ShaderRef {
+        UPSCALE_SHADER_HANDLE.typed().into()
+    }
+}
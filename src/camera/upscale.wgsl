@@ -0,0 +1,157 @@
+// filter in x, how many window pixels each canvas pixel covers in yz
+@group(1) @binding(0)
+var<uniform> settings: vec4<f32>;
+@group(1) @binding(1)
+var canvas: texture_2d<f32>;
+
+struct FragmentInput {
+    #import bevy_sprite::mesh2d_vertex_output
+};
+
+// Everything uses textureLoad so the canvas can keep its nearest sampler. These match the cpu versions in scale.rs
+fn pixel(position: vec2<i32>) -> vec4<f32> {
+    let size = vec2<i32>(textureDimensions(canvas));
+    return textureLoad(canvas, clamp(position, vec2<i32>(0), size - 1), 0);
+}
+
+fn same(a: vec4<f32>, b: vec4<f32>) -> bool {
+    return all(a == b);
+}
+
+fn scale2x(cell: vec2<i32>, sub: vec2<f32>) -> vec4<f32> {
+    let b = pixel(cell + vec2<i32>(0, -1));
+    let d = pixel(cell + vec2<i32>(-1, 0));
+    let e = pixel(cell);
+    let f = pixel(cell + vec2<i32>(1, 0));
+    let h = pixel(cell + vec2<i32>(0, 1));
+    if (same(b, h) || same(d, f)) {
+        return e;
+    }
+    let right = sub.x >= 0.5;
+    let down = sub.y >= 0.5;
+    if (!right && !down) {
+        return select(e, d, same(d, b));
+    }
+    if (right && !down) {
+        return select(e, f, same(b, f));
+    }
+    if (!right && down) {
+        return select(e, d, same(d, h));
+    }
+    return select(e, f, same(h, f));
+}
+
+fn scale3x(cell: vec2<i32>, sub: vec2<f32>) -> vec4<f32> {
+    let a = pixel(cell + vec2<i32>(-1, -1));
+    let b = pixel(cell + vec2<i32>(0, -1));
+    let c = pixel(cell + vec2<i32>(1, -1));
+    let d = pixel(cell + vec2<i32>(-1, 0));
+    let e = pixel(cell);
+    let f = pixel(cell + vec2<i32>(1, 0));
+    let g = pixel(cell + vec2<i32>(-1, 1));
+    let h = pixel(cell + vec2<i32>(0, 1));
+    let i = pixel(cell + vec2<i32>(1, 1));
+    if (same(b, h) || same(d, f)) {
+        return e;
+    }
+    let part = min(vec2<i32>(sub * 3.0), vec2<i32>(2));
+    let index = part.y * 3 + part.x;
+    switch index {
+        case 0: {
+            return select(e, d, same(d, b));
+        }
+        case 1: {
+            return select(e, b, (same(d, b) && !same(e, c)) || (same(b, f) && !same(e, a)));
+        }
+        case 2: {
+            return select(e, f, same(b, f));
+        }
+        case 3: {
+            return select(e, d, (same(d, b) && !same(e, g)) || (same(d, h) && !same(e, a)));
+        }
+        case 5: {
+            return select(e, f, (same(b, f) && !same(e, i)) || (same(h, f) && !same(e, c)));
+        }
+        case 6: {
+            return select(e, d, same(d, h));
+        }
+        case 7: {
+            return select(e, h, (same(d, h) && !same(e, i)) || (same(h, f) && !same(e, g)));
+        }
+        case 8: {
+            return select(e, f, same(h, f));
+        }
+        default: {
+            return e;
+        }
+    }
+}
+
+fn yuv_distance(a: vec4<f32>, b: vec4<f32>) -> f32 {
+    let difference = a.rgb - b.rgb;
+    let y = dot(difference, vec3<f32>(0.299, 0.587, 0.114));
+    let u = dot(difference, vec3<f32>(-0.169, -0.331, 0.5));
+    let v = dot(difference, vec3<f32>(0.5, -0.419, -0.081));
+    return 48.0 * abs(y) + 7.0 * abs(u) + 6.0 * abs(v);
+}
+
+// Neighbors are mirrored by `side` so the corner being filled is always the bottom right one
+fn xbr(cell: vec2<i32>, sub: vec2<f32>) -> vec4<f32> {
+    let side = vec2<i32>(select(-1, 1, sub.x >= 0.5), select(-1, 1, sub.y >= 0.5));
+    let e = pixel(cell);
+    let f = pixel(cell + vec2<i32>(1, 0) * side);
+    let h = pixel(cell + vec2<i32>(0, 1) * side);
+    let i = pixel(cell + vec2<i32>(1, 1) * side);
+    let anti_diagonal = yuv_distance(e, pixel(cell + vec2<i32>(1, -1) * side))
+        + yuv_distance(e, pixel(cell + vec2<i32>(-1, 1) * side))
+        + yuv_distance(i, pixel(cell + vec2<i32>(2, 0) * side))
+        + yuv_distance(i, pixel(cell + vec2<i32>(0, 2) * side))
+        + 4.0 * yuv_distance(h, f);
+    let diagonal = yuv_distance(h, pixel(cell + vec2<i32>(-1, 0) * side))
+        + yuv_distance(h, pixel(cell + vec2<i32>(1, 2) * side))
+        + yuv_distance(f, pixel(cell + vec2<i32>(0, -1) * side))
+        + yuv_distance(f, pixel(cell + vec2<i32>(2, 1) * side))
+        + 4.0 * yuv_distance(e, i);
+    if (anti_diagonal < diagonal && !same(e, f) && !same(e, h)) {
+        return select(h, f, yuv_distance(e, f) <= yuv_distance(e, h));
+    }
+    return e;
+}
+
+fn sharp_bilinear(texel: vec2<f32>) -> vec4<f32> {
+    let prescale = max(floor(settings.yz), vec2<f32>(1.0));
+    let region = 0.5 - 0.5 / prescale;
+    let center = fract(texel) - 0.5;
+    let offset = (center - clamp(center, -region, region)) * prescale + 0.5;
+    let position = floor(texel) + offset - 0.5;
+    let base = vec2<i32>(floor(position));
+    let t = position - floor(position);
+    let top = mix(pixel(base), pixel(base + vec2<i32>(1, 0)), t.x);
+    let bottom = mix(pixel(base + vec2<i32>(0, 1)), pixel(base + vec2<i32>(1, 1)), t.x);
+    return mix(top, bottom, t.y);
+}
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(canvas));
+    let texel = in.uv * size;
+    let cell = vec2<i32>(min(texel, size - 1.0));
+    let sub = fract(texel);
+    switch u32(settings.x) {
+        case 1u: {
+            return scale2x(cell, sub);
+        }
+        case 2u: {
+            return scale3x(cell, sub);
+        }
+        case 3u: {
+            return sharp_bilinear(texel);
+        }
+        case 4u: {
+            return xbr(cell, sub);
+        }
+        default: {
+            return pixel(cell);
+        }
+    }
+}
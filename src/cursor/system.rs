@@ -1,7 +1,11 @@
 use bevy::{prelude::*, render::view::RenderLayers, window::PrimaryWindow};
 
 use crate::{
-    camera::{plugin::CursorCameraTag, scaled::ScaledPixelProjection, texture::FinalCameraTag},
+    camera::{
+        plugin::{CursorCameraTag, CURSOR_RENDER_LAYER},
+        scaled::ScaledPixelProjection,
+        texture::FinalCameraTag,
+    },
     prelude::PixelCameraTag,
 };
 
@@ -138,13 +142,15 @@ pub fn update_cursor(
                             camera.viewport_to_world_2d(transform, cursor)
                         }) {
                             cursor_transform.translation = world_position.extend(0.0);
-                            cursor_transform.scale = Vec2::splat(pixel.zoom).extend(1.0);
+                            cursor_transform.scale =
+                                Vec2::new(pixel.zoom * pixel.pixel_aspect, pixel.zoom).extend(1.0);
                         } else if let Some(world_position) = window
                             .cursor_position()
                             .and_then(|cursor| camera.viewport_to_world_2d(transform, cursor))
                         {
                             cursor_transform.translation = world_position.extend(0.0);
-                            cursor_transform.scale = Vec2::splat(pixel.zoom).extend(1.0);
+                            cursor_transform.scale =
+                                Vec2::new(pixel.zoom * pixel.pixel_aspect, pixel.zoom).extend(1.0);
                         }
                     }
                 }
@@ -158,7 +164,7 @@ pub fn add_cursor(
     mut windows: Query<&mut Window, With<PrimaryWindow>>,
     mut cursor_query: Query<(&mut PixelCursor, Entity)>,
 ) {
-    let cursor_layer = RenderLayers::layer((RenderLayers::TOTAL_LAYERS - 3) as u8);
+    let cursor_layer = RenderLayers::layer(CURSOR_RENDER_LAYER);
     if let Ok((mut cursor_sprite, entity)) = cursor_query.get_single_mut() {
         if !cursor_sprite.init {
             if let Ok(mut window) = windows.get_single_mut() {
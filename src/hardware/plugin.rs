@@ -0,0 +1,170 @@
+use bevy::prelude::*;
+
+use crate::{
+    camera::{
+        scaled::{ScaledPixelCamera, ScaledPixelProjection},
+        texture::TexturePixelCamera,
+    },
+    limit::plugin::PixelLimPlugin,
+};
+
+use super::system::{apply_hardware_palette, apply_hardware_profile};
+
+/// In hardware order so index $xy is NES color $xy, including the duplicate blacks of the real PPU
+const NES_PALETTE: [u32; 64] = [
+    0x7c7c7c, 0x0000fc, 0x0000bc, 0x4428bc, 0x940084, 0xa80020, 0xa81000, 0x881400, 0x503000,
+    0x007800, 0x006800, 0x005800, 0x004058, 0x000000, 0x000000, 0x000000, 0xbcbcbc, 0x0078f8,
+    0x0058f8, 0x6844fc, 0xd800cc, 0xe40058, 0xf83800, 0xe45c10, 0xac7c00, 0x00b800, 0x00a800,
+    0x00a844, 0x008888, 0x000000, 0x000000, 0x000000, 0xf8f8f8, 0x3cbcfc, 0x6888fc, 0x9878f8,
+    0xf878f8, 0xf85898, 0xf87858, 0xfca044, 0xf8b800, 0xb8f818, 0x58d854, 0x58f898, 0x00e8d8,
+    0x787878, 0x000000, 0x000000, 0xfcfcfc, 0xa4e4fc, 0xb8b8f8, 0xd8b8f8, 0xf8b8f8, 0xf8a4c0,
+    0xf0d0b0, 0xfce0a8, 0xf8d878, 0xd8f878, 0xb8f8b8, 0xb8f8d8, 0x00fcfc, 0xf8d8f8, 0x000000,
+    0x000000,
+];
+
+const GAME_BOY_PALETTE: [u32; 4] = [0x0f380f, 0x306230, 0x8bac0f, 0x9bbc0f];
+
+const PICO8_PALETTE: [u32; 16] = [
+    0x000000, 0x1d2b53, 0x7e2553, 0x008751, 0xab5236, 0x5f574f, 0xc2c3c7, 0xfff1e8, 0xff004d,
+    0xffa300, 0xffec27, 0x00e436, 0x29adff, 0x83769c, 0xff77a8, 0xffccaa,
+];
+
+/// Presets for the limitations of common consoles. Pass one to [`crate::plugin::PixelPlugins`] and it sets up the
+/// sprite limits and any pixel camera you spawn to match the console
+#[derive(Resource, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum HardwareProfile {
+    Nes,
+    GameBoy,
+    Snes,
+    Pico8,
+    Genesis,
+}
+
+impl HardwareProfile {
+    pub fn resolution(&self) -> UVec2 {
+        match self {
+            HardwareProfile::Nes => UVec2::new(256, 240),
+            HardwareProfile::GameBoy => UVec2::new(160, 144),
+            HardwareProfile::Snes => UVec2::new(256, 224),
+            HardwareProfile::Pico8 => UVec2::new(128, 128),
+            HardwareProfile::Genesis => UVec2::new(320, 224),
+        }
+    }
+
+    /// The width of a pixel divided by its height on a tv
+    pub fn pixel_aspect(&self) -> f32 {
+        match self {
+            HardwareProfile::Nes | HardwareProfile::Snes => 8.0 / 7.0,
+            HardwareProfile::Genesis => 32.0 / 35.0,
+            HardwareProfile::GameBoy | HardwareProfile::Pico8 => 1.0,
+        }
+    }
+
+    /// 0 means there is no limit
+    pub fn sprite_count(&self) -> u32 {
+        match self {
+            HardwareProfile::Nes => 64,
+            HardwareProfile::GameBoy => 40,
+            HardwareProfile::Snes => 128,
+            HardwareProfile::Pico8 => 0,
+            HardwareProfile::Genesis => 80,
+        }
+    }
+
+    /// 0 means there is no limit
+    pub fn scanline_count(&self) -> u32 {
+        match self {
+            HardwareProfile::Nes => 8,
+            HardwareProfile::GameBoy => 10,
+            HardwareProfile::Snes => 32,
+            HardwareProfile::Pico8 => 0,
+            HardwareProfile::Genesis => 20,
+        }
+    }
+
+    /// The opaque colors a single sprite may use
+    pub fn sprite_colors(&self) -> Option<u32> {
+        match self {
+            HardwareProfile::Nes | HardwareProfile::GameBoy => Some(3),
+            HardwareProfile::Snes | HardwareProfile::Genesis => Some(15),
+            HardwareProfile::Pico8 => None,
+        }
+    }
+
+    /// The colors the console can show. None for consoles with too many colors to be worth limiting to
+    pub fn palette(&self) -> Option<Vec<Color>> {
+        let colors: &[u32] = match self {
+            HardwareProfile::Nes => &NES_PALETTE,
+            HardwareProfile::GameBoy => &GAME_BOY_PALETTE,
+            HardwareProfile::Pico8 => &PICO8_PALETTE,
+            HardwareProfile::Snes | HardwareProfile::Genesis => return None,
+        };
+        Some(
+            colors
+                .iter()
+                .map(|color| {
+                    let [_, r, g, b] = color.to_be_bytes();
+                    Color::rgb_u8(r, g, b)
+                })
+                .collect(),
+        )
+    }
+
+    pub fn scaled_camera(&self) -> ScaledPixelCamera {
+        let resolution = self.resolution();
+        ScaledPixelCamera::new(ScaledPixelProjection {
+            desired_width: Some(resolution.x as i32),
+            desired_height: Some(resolution.y as i32),
+            pixel_aspect: self.pixel_aspect(),
+            ..Default::default()
+        })
+    }
+
+    pub fn texture_camera(&self) -> TexturePixelCamera {
+        let resolution = self.resolution();
+        let mut camera = TexturePixelCamera::from_resolution(resolution.x, resolution.y);
+        camera.pixel_aspect = self.pixel_aspect();
+        camera
+    }
+
+    pub fn lim_plugin(&self) -> PixelLimPlugin {
+        PixelLimPlugin {
+            sprite_count: self.sprite_count(),
+            scanline_count: self.scanline_count(),
+            max_sprite_colors: self.sprite_colors(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Applies a [`HardwareProfile`] to every pixel camera that gets spawned and makes its palette the active one.
+/// Cameras keep any resolution or pixel aspect you gave them, only the defaults are replaced. The palette is only used
+/// when [`crate::palette::plugin::ActivePalette`] wasn't set to something else and needs the
+/// [`crate::palette::plugin::PixelPalettePlugin`].
+/// The limits are handled by [`PixelLimPlugin`] which [`crate::plugin::PixelPlugins`] adds alongside this
+pub struct PixelHardwarePlugin {
+    pub profile: HardwareProfile,
+}
+
+impl Plugin for PixelHardwarePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.profile)
+            .add_startup_system(apply_hardware_palette)
+            .add_system(apply_hardware_profile);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nes_palette_matches_hardware_indices() {
+        let palette = HardwareProfile::Nes.palette().unwrap();
+        assert_eq!(palette.len(), 64);
+        assert_eq!(palette[0x0f], Color::rgb_u8(0, 0, 0));
+        assert_eq!(palette[0x16], Color::rgb_u8(0xf8, 0x38, 0x00));
+        assert_eq!(palette[0x30], Color::rgb_u8(0xfc, 0xfc, 0xfc));
+        assert_eq!(palette[0x3d], Color::rgb_u8(0xf8, 0xd8, 0xf8));
+    }
+}
@@ -0,0 +1,120 @@
+use bevy::prelude::*;
+
+use crate::{
+    camera::{
+        scaled::{ScaledPixelCamera, ScaledPixelProjection},
+        texture::TexturePixelCamera,
+    },
+    palette::{plugin::ActivePalette, system::PixelPalette},
+};
+
+use super::plugin::HardwareProfile;
+
+/// Fills in the resolution and pixel aspect of newly spawned pixel cameras from the hardware profile.
+/// Both kinds of camera keep a size other than the default one, so scaled cameras made with a zoom keep it.
+/// Either keeps a pixel aspect other than 1
+pub fn apply_hardware_profile(
+    profile: Res<HardwareProfile>,
+    mut scaled_query: Query<&mut ScaledPixelProjection, Added<ScaledPixelProjection>>,
+    mut texture_query: Query<&mut TexturePixelCamera, Added<TexturePixelCamera>>,
+) {
+    let resolution = profile.resolution();
+    let default_projection = ScaledPixelCamera::default().pixel_projection;
+    for mut projection in scaled_query.iter_mut() {
+        if projection.desired_width == default_projection.desired_width
+            && projection.desired_height == default_projection.desired_height
+        {
+            projection.desired_width = Some(resolution.x as i32);
+            projection.desired_height = Some(resolution.y as i32);
+        }
+        if projection.pixel_aspect == 1.0 {
+            projection.pixel_aspect = profile.pixel_aspect();
+        }
+    }
+    for mut camera in texture_query.iter_mut() {
+        if camera.size == TexturePixelCamera::default().size {
+            camera.size = resolution;
+        }
+        if camera.pixel_aspect == 1.0 {
+            camera.pixel_aspect = profile.pixel_aspect();
+        }
+    }
+}
+
+/// Adds the palette of the hardware profile and makes it the active one unless another palette was set already.
+/// Runs at startup so it doesn't matter which order the plugins were added in
+pub fn apply_hardware_palette(
+    profile: Res<HardwareProfile>,
+    palettes: Option<ResMut<Assets<PixelPalette>>>,
+    active: Option<ResMut<ActivePalette>>,
+) {
+    let Some(colors) = profile.palette() else {
+        return;
+    };
+    let (Some(mut palettes), Some(mut active)) = (palettes, active) else {
+        warn!(
+            "The {:?} palette needs the PixelPalettePlugin, it won't be used",
+            *profile
+        );
+        return;
+    };
+    if active.palette == Handle::default() {
+        active.palette = palettes.add(PixelPalette::new(colors));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(profile: HardwareProfile) -> App {
+        let mut app = App::new();
+        app.insert_resource(profile)
+            .add_system(apply_hardware_profile);
+        app
+    }
+
+    #[test]
+    fn default_scaled_cameras_get_the_profile_resolution() {
+        let mut app = app(HardwareProfile::Nes);
+        let default = app.world.spawn(ScaledPixelCamera::default()).id();
+        let zoom = app.world.spawn(ScaledPixelCamera::from_zoom(3.0)).id();
+        let resolution = app
+            .world
+            .spawn(ScaledPixelCamera::from_resolution(320, 240, false))
+            .id();
+        app.update();
+        let desired = |entity| {
+            let projection = app.world.get::<ScaledPixelProjection>(entity).unwrap();
+            (
+                projection.desired_width,
+                projection.desired_height,
+                projection.pixel_aspect,
+            )
+        };
+        let aspect = HardwareProfile::Nes.pixel_aspect();
+        assert_eq!(desired(default), (Some(256), Some(240), aspect));
+        assert_eq!(desired(zoom), (None, None, aspect));
+        assert_eq!(desired(resolution), (Some(320), Some(240), aspect));
+        assert_eq!(
+            app.world.get::<ScaledPixelProjection>(zoom).unwrap().zoom,
+            3.0
+        );
+    }
+
+    #[test]
+    fn default_texture_cameras_get_the_profile_resolution() {
+        let mut app = app(HardwareProfile::GameBoy);
+        let default = app.world.spawn(TexturePixelCamera::default()).id();
+        let mut custom = TexturePixelCamera::new(UVec2::new(320, 180), None, Color::BLACK, false);
+        custom.pixel_aspect = 2.0;
+        let custom = app.world.spawn(custom).id();
+        app.update();
+        let size = |entity| {
+            let camera = app.world.get::<TexturePixelCamera>(entity).unwrap();
+            (camera.size, camera.pixel_aspect)
+        };
+        assert_eq!(size(default), (UVec2::new(160, 144), 1.0));
+        assert_eq!(size(custom), (UVec2::new(320, 180), 2.0));
+    }
+}
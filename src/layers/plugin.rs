@@ -1,7 +1,9 @@
-use bevy::prelude::*;
 use crate::prelude::PixelLayer;
+use bevy::{prelude::*, render::RenderApp};
 use extol_sprite_layer::*;
 
+use super::system::{map_render_layers, update_layer_offsets, PixelLayerRenderLayers};
+
 pub struct PixelLayerPlugin {
     pub y_sort: bool,
 }
@@ -14,5 +16,14 @@ impl Plugin for PixelLayerPlugin {
         } else {
             app.insert_resource(SpriteLayerOptions { y_sort: false });
         }
+        app.init_resource::<PixelLayerRenderLayers>()
+            .add_system(map_render_layers);
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app.add_system(
+                update_layer_offsets
+                    .after(SpriteLayerSet)
+                    .in_schedule(ExtractSchedule),
+            );
+        }
     }
 }
@@ -1,4 +1,9 @@
-use bevy::prelude::*;
+use bevy::{
+    prelude::*,
+    render::{view::RenderLayers, Extract},
+    sprite::ExtractedSprites,
+    utils::HashMap,
+};
 use extol_sprite_layer::*;
 
 /// A way to sort sprites based off of layer. The number you use inside of the enums also affect ordering
@@ -19,3 +24,333 @@ impl LayerIndex for PixelLayer {
         }
     }
 }
+
+/// Routes whole PixelLayers to specific cameras. Every entity in a mapped layer gets the matching RenderLayers inserted,
+/// children with a PixelLayerOffset follow their layered ancestor. Ie mapping a hud layer to [`crate::camera::plugin::UI_RENDER_LAYER`]
+/// keeps it on the full resolution camera while everything else stays on the pixel camera. Entities that already have
+/// RenderLayers of their own are left alone.
+#[derive(Resource, Default, Clone, Deref, DerefMut)]
+pub struct PixelLayerRenderLayers(pub HashMap<PixelLayer, RenderLayers>);
+
+impl PixelLayerRenderLayers {
+    pub fn with(mut self, layer: PixelLayer, render_layers: RenderLayers) -> Self {
+        self.insert(layer, render_layers);
+        self
+    }
+}
+
+/// Holds the RenderLayers that were inserted by [`map_render_layers`] so they can be removed again once the layer is unmapped.
+/// If the RenderLayers no longer match them they were changed by someone else and are left alone from then on
+#[derive(Component, Copy, Clone)]
+pub struct MappedRenderLayers(pub RenderLayers);
+
+/// Used on child sprites instead of a PixelLayer. The child is drawn in the same layer as its closest ancestor with a PixelLayer
+/// and the offset decides if it is drawn above(positive) or below(negative) it. This also holds up when y sorting is turned on.
+#[derive(Debug, Default, Copy, Clone, Component, PartialEq, Eq, Hash)]
+pub struct PixelLayerOffset(pub i8);
+
+/// The gap between a float and the next one up. Offsets smaller than this would round back onto the parent
+fn ulp(z: f32) -> f32 {
+    let z = z.abs();
+    f32::from_bits(z.to_bits() + 1) - z
+}
+
+/// The z every layered entity gets from y sorting, worked out the same way the sprite layer plugin does it. Used for
+/// layered ancestors that aren't sprites themselves, like the root of a metasprite
+fn y_sorted_z(
+    transform_query: &Query<(Entity, &GlobalTransform), With<PixelLayer>>,
+    layer_query: &Query<&PixelLayer>,
+) -> HashMap<Entity, f32> {
+    let mut entities = transform_query
+        .iter()
+        .map(|(entity, transform)| (transform.translation().y, entity))
+        .collect::<Vec<_>>();
+    entities.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    let scale = 1.0 / entities.len().max(1) as f32;
+    entities
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, (_, entity))| {
+            let layer = layer_query.get(entity).ok()?;
+            Some((entity, layer.as_z_coordinate() + index as f32 * scale))
+        })
+        .collect()
+}
+
+/// Runs in the render world right after the sprite layers have been applied and moves every sprite with a PixelLayerOffset
+/// just above or below its layered ancestor.
+pub fn update_layer_offsets(
+    mut extracted_sprites: ResMut<ExtractedSprites>,
+    options: Extract<Res<SpriteLayerOptions>>,
+    offset_query: Extract<Query<&PixelLayerOffset>>,
+    parent_query: Extract<Query<&Parent>>,
+    layer_query: Extract<Query<&PixelLayer>>,
+    transform_query: Extract<Query<(Entity, &GlobalTransform), With<PixelLayer>>>,
+) {
+    // y sorting spreads the sprites of a layer 1 / count apart so the offsets need to stay well within that gap
+    let step = 1.0 / layer_query.iter().count().max(1) as f32 / 256.0;
+    let mut layered_z = extracted_sprites
+        .sprites
+        .iter()
+        .filter(|sprite| layer_query.contains(sprite.entity))
+        .map(|sprite| (sprite.entity, sprite.transform.translation().z))
+        .collect::<HashMap<_, _>>();
+    let mut sorted = None;
+
+    for sprite in extracted_sprites.sprites.iter_mut() {
+        if let Ok(offset) = offset_query.get(sprite.entity) {
+            let mut ancestor = parent_query.get(sprite.entity).ok().map(Parent::get);
+            while let Some(entity) = ancestor {
+                if let Ok(layer) = layer_query.get(entity) {
+                    let parent_z =
+                        *layered_z
+                            .entry(entity)
+                            .or_insert_with(|| match options.y_sort {
+                                true => sorted
+                                    .get_or_insert_with(|| {
+                                        y_sorted_z(&transform_query, &layer_query)
+                                    })
+                                    .get(&entity)
+                                    .copied()
+                                    .unwrap_or_else(|| layer.as_z_coordinate()),
+                                false => layer.as_z_coordinate(),
+                            });
+                    // With lots of sprites the gap can get smaller than what a float can tell apart at this z
+                    let step = step.max(ulp(parent_z));
+                    let mut affine = sprite.transform.affine();
+                    affine.translation.z = parent_z + offset.0 as f32 * step;
+                    sprite.transform = GlobalTransform::from(affine);
+                    break;
+                }
+                ancestor = parent_query.get(entity).ok().map(Parent::get);
+            }
+        }
+    }
+}
+
+/// The PixelLayer of the entity or of its closest ancestor that has one
+pub fn closest_layer(
+    entity: Entity,
+    layer_query: &Query<&PixelLayer>,
+    parent_query: &Query<&Parent>,
+) -> Option<PixelLayer> {
+    let mut ancestor = Some(entity);
+    while let Some(entity) = ancestor {
+        if let Ok(layer) = layer_query.get(entity) {
+            return Some(*layer);
+        }
+        ancestor = parent_query.get(entity).ok().map(Parent::get);
+    }
+    None
+}
+
+/// Keeps the RenderLayers of every layered sprite in line with [`PixelLayerRenderLayers`]. Only touches entities whose mapping actually changed
+#[allow(clippy::type_complexity)]
+pub fn map_render_layers(
+    mut commands: Commands,
+    layer_map: Res<PixelLayerRenderLayers>,
+    sprite_query: Query<
+        (Entity, Option<&RenderLayers>, Option<&MappedRenderLayers>),
+        Or<(With<PixelLayer>, With<PixelLayerOffset>)>,
+    >,
+    layer_query: Query<&PixelLayer>,
+    parent_query: Query<&Parent>,
+) {
+    for (entity, render_layers, mapped) in sprite_query.iter() {
+        match (render_layers, mapped) {
+            // RenderLayers that were set by hand are never touched
+            (Some(_), None) => continue,
+            (Some(current), Some(mapped)) if *current != mapped.0 => {
+                commands.entity(entity).remove::<MappedRenderLayers>();
+                continue;
+            }
+            _ => (),
+        }
+        let wanted = closest_layer(entity, &layer_query, &parent_query)
+            .and_then(|layer| layer_map.get(&layer).copied());
+        match wanted {
+            Some(wanted) => {
+                if render_layers != Some(&wanted) {
+                    commands
+                        .entity(entity)
+                        .insert((wanted, MappedRenderLayers(wanted)));
+                }
+            }
+            None => {
+                if mapped.is_some() {
+                    commands
+                        .entity(entity)
+                        .remove::<(RenderLayers, MappedRenderLayers)>();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{asset::HandleId, render::MainWorld, sprite::ExtractedSprite};
+
+    use super::*;
+
+    fn app(layer_map: PixelLayerRenderLayers) -> App {
+        let mut app = App::new();
+        app.insert_resource(layer_map).add_system(map_render_layers);
+        app
+    }
+
+    #[test]
+    fn mapped_layers_get_render_layers() {
+        let mut app = app(PixelLayerRenderLayers::default()
+            .with(PixelLayer::Foreground(1), RenderLayers::layer(3)));
+        let mapped = app.world.spawn(PixelLayer::Foreground(1)).id();
+        let unmapped = app.world.spawn(PixelLayer::Foreground(2)).id();
+        let child = app.world.spawn(PixelLayerOffset(1)).id();
+        app.world.entity_mut(mapped).push_children(&[child]);
+        app.update();
+        assert_eq!(
+            app.world.get::<RenderLayers>(mapped),
+            Some(&RenderLayers::layer(3))
+        );
+        assert_eq!(
+            app.world.get::<RenderLayers>(child),
+            Some(&RenderLayers::layer(3))
+        );
+        assert_eq!(app.world.get::<RenderLayers>(unmapped), None);
+    }
+
+    #[test]
+    fn unmapping_removes_only_our_render_layers() {
+        let mut app = app(PixelLayerRenderLayers::default()
+            .with(PixelLayer::Foreground(1), RenderLayers::layer(3)));
+        let mapped = app.world.spawn(PixelLayer::Foreground(1)).id();
+        app.update();
+        app.world.resource_mut::<PixelLayerRenderLayers>().clear();
+        app.update();
+        assert_eq!(app.world.get::<RenderLayers>(mapped), None);
+        assert!(app.world.get::<MappedRenderLayers>(mapped).is_none());
+    }
+
+    #[test]
+    fn hand_set_render_layers_are_left_alone() {
+        let mut app = app(PixelLayerRenderLayers::default()
+            .with(PixelLayer::Foreground(1), RenderLayers::layer(3)));
+        let manual = app
+            .world
+            .spawn((PixelLayer::Foreground(1), RenderLayers::layer(5)))
+            .id();
+        let changed = app.world.spawn(PixelLayer::Foreground(1)).id();
+        app.update();
+        app.world.entity_mut(changed).insert(RenderLayers::layer(6));
+        app.update();
+        app.world.resource_mut::<PixelLayerRenderLayers>().clear();
+        app.update();
+        assert_eq!(
+            app.world.get::<RenderLayers>(manual),
+            Some(&RenderLayers::layer(5))
+        );
+        assert_eq!(
+            app.world.get::<RenderLayers>(changed),
+            Some(&RenderLayers::layer(6))
+        );
+    }
+
+    fn extracted(entity: Entity, z: f32) -> ExtractedSprite {
+        ExtractedSprite {
+            entity,
+            transform: GlobalTransform::from_xyz(0.0, 0.0, z),
+            color: Color::WHITE,
+            rect: None,
+            custom_size: None,
+            image_handle_id: HandleId::default::<Image>(),
+            flip_x: false,
+            flip_y: false,
+            anchor: Vec2::ZERO,
+        }
+    }
+
+    /// Runs [`update_layer_offsets`] like the render world would and hands back the z of every extracted sprite
+    fn offset_z(main: World, sprites: Vec<ExtractedSprite>) -> HashMap<Entity, f32> {
+        let mut world = World::new();
+        let mut main_world = MainWorld::default();
+        *main_world = main;
+        world.insert_resource(main_world);
+        world.insert_resource(ExtractedSprites { sprites });
+        let mut schedule = Schedule::new();
+        schedule.add_system(update_layer_offsets);
+        schedule.run(&mut world);
+        world
+            .resource::<ExtractedSprites>()
+            .sprites
+            .iter()
+            .map(|sprite| (sprite.entity, sprite.transform.translation().z))
+            .collect()
+    }
+
+    #[test]
+    fn ulp_is_the_gap_to_the_next_float() {
+        assert_eq!(ulp(1.0), f32::EPSILON);
+        assert_eq!(ulp(-1.0), f32::EPSILON);
+        assert!(ulp(511.0) > ulp(1.0));
+        assert_ne!(511.0 + ulp(511.0), 511.0);
+    }
+
+    #[test]
+    fn offsets_sort_children_around_their_parent() {
+        let mut main = World::new();
+        main.insert_resource(SpriteLayerOptions { y_sort: false });
+        let layer = PixelLayer::Foreground(1);
+        let parent = main.spawn((layer, GlobalTransform::default())).id();
+        let above = main.spawn(PixelLayerOffset(1)).id();
+        let below = main.spawn(PixelLayerOffset(-1)).id();
+        // Grandchildren follow the closest layered ancestor
+        let nested = main.spawn(PixelLayerOffset(2)).id();
+        main.entity_mut(parent).push_children(&[above, below]);
+        main.entity_mut(above).push_children(&[nested]);
+        let parent_z = layer.as_z_coordinate();
+
+        let z = offset_z(
+            main,
+            vec![
+                extracted(parent, parent_z),
+                extracted(above, 0.0),
+                extracted(below, 0.0),
+                extracted(nested, 0.0),
+            ],
+        );
+        assert_eq!(z[&parent], parent_z);
+        assert!(z[&below] < parent_z);
+        assert!(z[&above] > parent_z);
+        assert!(z[&nested] > z[&above]);
+        // Staying well inside the layer so nothing crosses into the next one
+        assert!(z[&nested] < PixelLayer::Foreground(2).as_z_coordinate());
+        assert!(z[&below] > PixelLayer::Foreground(0).as_z_coordinate());
+    }
+
+    #[test]
+    fn offsets_follow_y_sorted_ancestors_without_sprites() {
+        let mut main = World::new();
+        main.insert_resource(SpriteLayerOptions { y_sort: true });
+        let layer = PixelLayer::Foreground(1);
+        // Neither root is a sprite so their z has to be worked out from y like the layer plugin does
+        let high = main
+            .spawn((layer, GlobalTransform::from_xyz(0.0, 10.0, 0.0)))
+            .id();
+        let low = main
+            .spawn((layer, GlobalTransform::from_xyz(0.0, -10.0, 0.0)))
+            .id();
+        let high_child = main.spawn(PixelLayerOffset(-1)).id();
+        let low_child = main.spawn(PixelLayerOffset(-1)).id();
+        main.entity_mut(high).push_children(&[high_child]);
+        main.entity_mut(low).push_children(&[low_child]);
+
+        let z = offset_z(
+            main,
+            vec![extracted(high_child, 0.0), extracted(low_child, 0.0)],
+        );
+        // The lower root is closer to the viewer so even its bottom child is drawn over the higher one
+        assert!(z[&low_child] > z[&high_child]);
+        assert!(z[&high_child] < layer.as_z_coordinate() + 0.5);
+        assert!(z[&low_child] > layer.as_z_coordinate());
+    }
+}
@@ -1,18 +1,60 @@
+pub mod alpha;
 pub mod camera;
 pub mod cursor;
+pub mod hardware;
 pub mod layers;
 pub mod limit;
+pub mod metasprite;
+pub mod palette;
 pub mod plugin;
+pub mod rotation;
+pub mod scale;
 
 pub mod prelude {
+    pub use crate::alpha::plugin::AlphaThreshold;
+    pub use crate::alpha::plugin::AlphaThresholds;
+    pub use crate::alpha::plugin::PixelAlphaPlugin;
+    pub use crate::alpha::system::DitherAlpha;
     pub use crate::camera::plugin::PixelCameraPlugin;
     pub use crate::camera::plugin::PixelCameraTag;
     pub use crate::camera::scaled::ScaledPixelCamera;
     pub use crate::camera::texture::TexturePixelCamera;
+    pub use crate::camera::upscale::UpscaleFilter;
     pub use crate::cursor::plugin::PixelCursorPlugin;
     pub use crate::cursor::system::PixelCursor;
+    pub use crate::hardware::plugin::HardwareProfile;
+    pub use crate::hardware::plugin::PixelHardwarePlugin;
     pub use crate::layers::plugin::PixelLayerPlugin;
     pub use crate::layers::system::PixelLayer;
+    pub use crate::layers::system::PixelLayerOffset;
+    pub use crate::layers::system::PixelLayerRenderLayers;
+    pub use crate::limit::plugin::LimitCulled;
     pub use crate::limit::plugin::PixelLimPlugin;
+    pub use crate::limit::plugin::SpritePriority;
+    pub use crate::metasprite::plugin::PixelMetaspritePlugin;
+    pub use crate::metasprite::system::Metasprite;
+    pub use crate::metasprite::system::MetaspriteTile;
+    pub use crate::metasprite::system::TileSize;
+    pub use crate::palette::cycle::CycleDirection;
+    pub use crate::palette::cycle::PaletteCycle;
+    pub use crate::palette::daynight::PaletteKeyframe;
+    pub use crate::palette::fade::FadeTarget;
+    pub use crate::palette::indexed::IndexedImage;
+    pub use crate::palette::indexed::IndexedSprite;
+    pub use crate::palette::indexed::IndexedSpriteBundle;
+    pub use crate::palette::indexed::PaletteMaterial;
+    pub use crate::palette::loader::PixelPaletteLoader;
+    pub use crate::palette::plugin::ActivePalette;
+    pub use crate::palette::plugin::DayNightCycle;
+    pub use crate::palette::plugin::LayerPalettes;
+    pub use crate::palette::plugin::PaletteFade;
+    pub use crate::palette::plugin::PaletteFadeFinished;
+    pub use crate::palette::plugin::PixelPalettePlugin;
+    pub use crate::palette::plugin::QuantizedImages;
+    pub use crate::palette::quantize::Dither;
+    pub use crate::palette::swap::PaletteSwap;
+    pub use crate::palette::system::PixelPalette;
     pub use crate::plugin::PixelPlugins;
+    pub use crate::rotation::plugin::PixelRotationPlugin;
+    pub use crate::rotation::system::PixelRotation;
 }
@@ -1,46 +1,191 @@
-use bevy::prelude::*;
+use bevy::{
+    prelude::*,
+    render::{view::VisibilitySystems, RenderApp},
+    sprite::{extract_sprites, SpriteSystem},
+    utils::HashSet,
+};
 
-use super::system::sprite_count_limiter;
+use crate::palette::system::PixelPalette;
+
+use super::system::{
+    check_sprite_colors, check_sprite_palettes, check_sprite_sizes, remove_culled_sprites,
+    remove_rejected_sprites, require_palette_plugin, sprite_count_limiter, ImageRegion,
+};
 
 /// The plugin that handles everything related to limitations such as sprite count, palette, etc
 /// A sprite count of 0 means that this limitation is disabled
+/// A scanline count above 0 also limits how many sprites may share a single row of virtual pixels(8 on the NES)
+/// With flicker turned on the dropped sprites are rotated each step like the NES did, flicker_interval is how many frames
+/// each step lasts. Raise it to slow the flicker down for people sensitive to flashing
+/// max_sprite_size reports any sprite bigger than it with a [`SpriteSizeViolation`] and crops it if crop_oversized is set
+/// max_sprite_colors does the same for images with too many opaque colors(3 on the NES). With strict_colors those sprites aren't drawn
+/// check_palettes reports images with colors outside the palette of their layer with a [`SpritePaletteViolation`], see
+/// [`crate::palette::plugin::LayerPalettes`]. Each sprite is reported once until the count for it changes. This needs the
+/// [`crate::palette::plugin::PixelPalettePlugin`] and panics at startup without it
 pub struct PixelLimPlugin {
     pub sprite_count: u32,
-    pub random: bool,
+    pub scanline_count: u32,
+    pub flicker: bool,
+    pub flicker_interval: u32,
+    pub max_sprite_size: Option<UVec2>,
+    pub crop_oversized: bool,
+    pub max_sprite_colors: Option<u32>,
+    pub strict_colors: bool,
+    pub check_palettes: bool,
 }
 
 #[derive(Resource, Clone, Copy)]
 pub struct SpriteCount {
     pub count: u32,
-    pub random: bool,
+    pub scanline_count: u32,
+    pub flicker: bool,
+    pub flicker_interval: u32,
+}
+
+#[derive(Resource, Clone, Copy)]
+pub struct SpriteSizeLimit {
+    pub max: UVec2,
+    pub crop: bool,
+}
+
+/// Sent when a sprite is bigger than the max sprite size
+#[derive(Debug, Clone, Copy)]
+pub struct SpriteSizeViolation {
+    pub entity: Entity,
+    pub size: Vec2,
+    pub max: UVec2,
+}
+
+/// `rejected` holds the image regions that went over the limit, these aren't drawn when strict is set
+#[derive(Resource, Clone)]
+pub struct SpriteColorLimit {
+    pub max: u32,
+    pub strict: bool,
+    pub rejected: HashSet<ImageRegion>,
+}
+
+/// Sent when the part of an image a sprite shows has more opaque colors than allowed
+#[derive(Debug, Clone)]
+pub struct SpriteColorViolation {
+    pub image: Handle<Image>,
+    pub rect: Option<Rect>,
+    pub colors: u32,
+    pub max: u32,
+}
+
+/// Sent when a sprite uses an image with colors that aren't in the palette of its layer
+#[derive(Debug, Clone)]
+pub struct SpritePaletteViolation {
+    pub entity: Entity,
+    pub image: Handle<Image>,
+    pub palette: Handle<PixelPalette>,
+    /// How many opaque colors aren't in the palette
+    pub colors: u32,
+}
+
+/// Decides which sprites get dropped first when there are too many. Higher priorities get a slot first and sprites
+/// with the same priority are ordered by entity so the same ones are dropped each frame. Sprites without one have a priority of 0
+#[derive(Component, Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SpritePriority(pub i32);
+
+impl SpritePriority {
+    /// Sprites with this priority are never dropped. They still use up the budget of other sprites though
+    pub const ALWAYS: Self = Self(i32::MAX);
 }
 
+/// Added to sprites the limiter decided not to draw this frame. Their own Visibility is left untouched but children of
+/// a culled sprite aren't drawn either, like they would be with a hidden parent
+#[derive(Component, Debug, Default, Copy, Clone)]
+pub struct LimitCulled;
+
 impl Default for PixelLimPlugin {
     fn default() -> Self {
         Self {
             sprite_count: 32,
-            random: false,
+            scanline_count: 0,
+            flicker: false,
+            flicker_interval: 1,
+            max_sprite_size: None,
+            crop_oversized: false,
+            max_sprite_colors: None,
+            strict_colors: false,
+            check_palettes: false,
         }
     }
 }
 
 impl PixelLimPlugin {
-    pub fn new(sprite_count: u32, random: bool) -> Self {
+    pub fn new(sprite_count: u32, flicker: bool) -> Self {
         Self {
             sprite_count,
-            random,
+            flicker,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_scanlines(sprite_count: u32, scanline_count: u32, flicker: bool) -> Self {
+        Self {
+            sprite_count,
+            scanline_count,
+            flicker,
+            ..Default::default()
         }
     }
 }
 
 impl Plugin for PixelLimPlugin {
     fn build(&self, app: &mut App) {
-        if self.sprite_count != 0 {
+        if self.sprite_count != 0 || self.scanline_count != 0 {
             app.insert_resource(SpriteCount {
                 count: self.sprite_count,
-                random: self.random,
+                scanline_count: self.scanline_count,
+                flicker: self.flicker,
+                flicker_interval: self.flicker_interval,
+            })
+            // Runs once visibility is known for this frame so sprites hidden by the user don't use up the budget
+            .add_system(
+                sprite_count_limiter
+                    .in_base_set(CoreSet::PostUpdate)
+                    .after(VisibilitySystems::CheckVisibility),
+            );
+            if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+                render_app.add_system(
+                    remove_culled_sprites
+                        .after(extract_sprites)
+                        .in_set(SpriteSystem::ExtractSprites)
+                        .in_schedule(ExtractSchedule),
+                );
+            }
+        }
+        if let Some(max) = self.max_sprite_size {
+            app.insert_resource(SpriteSizeLimit {
+                max,
+                crop: self.crop_oversized,
             })
-            .add_system(sprite_count_limiter);
+            .add_event::<SpriteSizeViolation>()
+            .add_system(check_sprite_sizes);
+        }
+        if let Some(max) = self.max_sprite_colors {
+            app.insert_resource(SpriteColorLimit {
+                max,
+                strict: self.strict_colors,
+                rejected: HashSet::default(),
+            })
+            .add_event::<SpriteColorViolation>()
+            .add_system(check_sprite_colors);
+            if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+                render_app.add_system(
+                    remove_rejected_sprites
+                        .after(extract_sprites)
+                        .in_set(SpriteSystem::ExtractSprites)
+                        .in_schedule(ExtractSchedule),
+                );
+            }
+        }
+        if self.check_palettes {
+            app.add_event::<SpritePaletteViolation>()
+                .add_startup_system(require_palette_plugin)
+                .add_system(check_sprite_palettes);
         }
     }
 }
@@ -1,35 +1,961 @@
+use bevy::asset::HandleId;
 use bevy::prelude::*;
-use rand::prelude::SliceRandom;
-use rand::thread_rng;
+use bevy::render::render_resource::TextureFormat;
+use bevy::render::Extract;
+use bevy::sprite::ExtractedSprites;
+use bevy::utils::{HashMap, HashSet};
 
+use crate::camera::plugin::PixelCameraTag;
+use crate::camera::scaled::ScaledPixelProjection;
+use crate::camera::texture::TexturePixelCamera;
+use crate::metasprite::system::Metasprite;
+use crate::palette::quantize::rgba_u8;
+use crate::palette::system::{rgba_pixels, PixelPalette, SpritePalettes};
 use crate::plugin::PixelSprite;
+use crate::rotation::system::PixelRotationSource;
 
-use super::plugin::SpriteCount;
+use super::plugin::{
+    LimitCulled, SpriteColorLimit, SpriteColorViolation, SpriteCount, SpritePaletteViolation,
+    SpritePriority, SpriteSizeLimit, SpriteSizeViolation,
+};
 
+/// The size a sprite is drawn at before any scaling
+pub fn sprite_size(sprite: &Sprite, image_size: Option<Vec2>) -> Option<Vec2> {
+    sprite
+        .custom_size
+        .or_else(|| sprite.rect.map(|rect| rect.size()))
+        .or(image_size)
+}
+
+/// The area a sprite covers in world space. Rotation is ignored as hardware sprites can't rotate anyways
+pub fn sprite_rect(transform: &GlobalTransform, sprite: &Sprite, image_size: Option<Vec2>) -> Rect {
+    let size = sprite_size(sprite, image_size).unwrap_or(Vec2::ZERO);
+    let (scale, _, translation) = transform.to_scale_rotation_translation();
+    let size = size * scale.truncate().abs();
+    let min = translation.truncate() - (sprite.anchor.as_vec() + Vec2::splat(0.5)) * size;
+    Rect::from_corners(min, min + size)
+}
+
+/// The area of the world a pixel camera can see. Virtual pixels line up with world units for both cameras
+pub fn camera_view(
+    transform: &GlobalTransform,
+    scaled: Option<&ScaledPixelProjection>,
+    texture: Option<&TexturePixelCamera>,
+) -> Rect {
+    let position = transform.translation().truncate();
+    if let Some(projection) = scaled {
+        Rect::new(
+            position.x + projection.left,
+            position.y + projection.bottom,
+            position.x + projection.right,
+            position.y + projection.top,
+        )
+    } else if let Some(texture) = texture {
+        Rect::from_center_size(position, texture.size.as_vec2())
+    } else {
+        Rect::from_center_size(position, Vec2::ZERO)
+    }
+}
+
+/// Keeps track of how many sprites are on each virtual pixel row of the screen
+#[derive(Default)]
+pub struct Scanlines {
+    pub rows: HashMap<i32, u32>,
+}
+
+impl Scanlines {
+    /// The rows a sprite touches. `origin` is the bottom of the view so rows line up with the pixel grid of the camera
+    pub fn rows(rect: Rect, origin: f32) -> std::ops::Range<i32> {
+        (rect.min.y - origin).floor() as i32..(rect.max.y - origin).ceil() as i32
+    }
+
+    /// Returns true and takes up a slot on every row if none of the rows are full
+    pub fn try_add(&mut self, rows: std::ops::Range<i32>, limit: u32) -> bool {
+        if limit == 0 {
+            return true;
+        }
+        if rows
+            .clone()
+            .any(|row| self.rows.get(&row).copied().unwrap_or(0) >= limit)
+        {
+            return false;
+        }
+        self.add(rows);
+        true
+    }
+
+    /// Takes up a slot on every row even if they are already full
+    pub fn add(&mut self, rows: std::ops::Range<i32>) {
+        for row in rows {
+            *self.rows.entry(row).or_insert(0) += 1;
+        }
+    }
+}
+
+/// Where the flicker cycle currently is. Each step starts at the first sprite the step before had to drop, so the
+/// sprites that were shown move to the back of the line and every sprite ends up being visible for an even share of
+/// the frames. This is kept as an entity instead of a position so sprites going on or off screen don't skip anyone
+#[derive(Default)]
+pub struct FlickerCycle {
+    pub start: Option<Entity>,
+    pub frame: u32,
+}
+
+/// The sprite budget of a single pixel camera. Without a view every sprite counts towards it
+#[derive(Default)]
+pub struct ScreenBudget {
+    pub view: Option<Rect>,
+    pub count: u32,
+    pub scanlines: Scanlines,
+}
+
+impl ScreenBudget {
+    pub fn new(view: Rect) -> Self {
+        Self {
+            view: Some(view),
+            ..Default::default()
+        }
+    }
+
+    /// Whether the sprite is on this screen at all
+    pub fn sees(&self, rect: Rect) -> bool {
+        match self.view {
+            Some(view) if rect.is_empty() => view.contains(rect.center()),
+            Some(view) => !view.intersect(rect).is_empty(),
+            None => true,
+        }
+    }
+
+    /// The rows of this screen the sprite touches
+    pub fn rows(&self, rect: Rect) -> std::ops::Range<i32> {
+        if rect.is_empty() {
+            0..0
+        } else {
+            Scanlines::rows(rect, self.view.map_or(0.0, |view| view.min.y))
+        }
+    }
+}
+
+/// Hides the sprites that go over the limits. Only sprites that are on screen count and each pixel camera has its own budget,
+/// a sprite seen by more than one camera is drawn as long as one of them had room for it.
+#[allow(clippy::type_complexity)]
 pub fn sprite_count_limiter(
+    mut commands: Commands,
     sprite_count: Res<SpriteCount>,
-    mut query: Query<&mut Visibility, With<PixelSprite>>,
+    query: Query<
+        (
+            Entity,
+            Option<&LimitCulled>,
+            &GlobalTransform,
+            Option<&Sprite>,
+            Option<&Handle<Image>>,
+            Option<&SpritePriority>,
+        ),
+        (With<PixelSprite>, Without<Metasprite>),
+    >,
+    camera_query: Query<
+        (
+            &GlobalTransform,
+            Option<&ScaledPixelProjection>,
+            Option<&TexturePixelCamera>,
+        ),
+        With<PixelCameraTag>,
+    >,
+    visibility_query: Query<&ComputedVisibility>,
+    images: Res<Assets<Image>>,
+    mut cycle: Local<FlickerCycle>,
 ) {
-    let mut count = 0;
-    if sprite_count.random {
-        let mut collected_visibilities = query.iter_mut().collect::<Vec<_>>();
-        collected_visibilities.shuffle(&mut thread_rng());
-        for visibility in collected_visibilities.iter_mut() {
-            count += 1;
-            if count > sprite_count.count {
-                **visibility = Visibility::Hidden;
-            } else {
-                **visibility = Visibility::Inherited;
+    let mut budgets = camera_query
+        .iter()
+        .map(|(transform, scaled, texture)| {
+            ScreenBudget::new(camera_view(transform, scaled, texture))
+        })
+        .collect::<Vec<_>>();
+    if budgets.is_empty() {
+        budgets.push(ScreenBudget::default());
+    }
+    let mut dropped = HashSet::new();
+    // Sprites hidden by the user don't take up any of the budget
+    let mut collected_sprites = query
+        .iter()
+        .filter(|(entity, ..)| {
+            visibility_query
+                .get(*entity)
+                .map_or(true, ComputedVisibility::is_visible_in_hierarchy)
+        })
+        .collect::<Vec<_>>();
+    collected_sprites.sort_unstable_by_key(|(entity, ..)| entity.index());
+    if let (true, Some(start)) = (sprite_count.flicker, cycle.start) {
+        // If the sprite is gone the one after it takes its place
+        let offset = collected_sprites
+            .iter()
+            .position(|(entity, ..)| entity.index() >= start.index())
+            .unwrap_or(0);
+        collected_sprites.rotate_left(offset);
+    }
+    let order = collected_sprites
+        .iter()
+        .map(|(entity, ..)| *entity)
+        .collect::<Vec<_>>();
+    // Stable so the rotation is kept between sprites with the same priority
+    collected_sprites.sort_by_key(|(_, _, _, _, _, priority)| {
+        std::cmp::Reverse(priority.copied().unwrap_or_default())
+    });
+    for (entity, culled, transform, sprite, image, priority) in collected_sprites {
+        let rect = match sprite {
+            Some(sprite) => {
+                let image_size = image
+                    .and_then(|image| images.get(image))
+                    .map(|image| image.size());
+                sprite_rect(transform, sprite, image_size)
+            }
+            None => Rect::from_center_size(transform.translation().truncate(), Vec2::ZERO),
+        };
+        let always = priority.copied() == Some(SpritePriority::ALWAYS);
+        let mut seen = false;
+        let mut kept = false;
+        for budget in budgets.iter_mut().filter(|budget| budget.sees(rect)) {
+            seen = true;
+            let rows = budget.rows(rect);
+            if always {
+                budget.count += 1;
+                budget.scanlines.add(rows);
+            } else if (sprite_count.count == 0 || budget.count < sprite_count.count)
+                && budget.scanlines.try_add(rows, sprite_count.scanline_count)
+            {
+                budget.count += 1;
+                kept = true;
             }
         }
-    } else {
-        for mut visibility in query.iter_mut() {
-            count += 1;
-            if count > sprite_count.count {
-                *visibility = Visibility::Hidden;
-            } else {
-                *visibility = Visibility::Inherited;
+        // Off screen sprites are left alone as there is nothing to draw anyways
+        let visible = always || kept || !seen;
+        if seen && !always && !kept {
+            dropped.insert(entity);
+        }
+        // Only touch the entity when the decision flips so change detection stays quiet
+        match (visible, culled.is_some()) {
+            (true, true) => {
+                commands.entity(entity).remove::<LimitCulled>();
+            }
+            (false, false) => {
+                commands.entity(entity).insert(LimitCulled);
+            }
+            _ => (),
+        }
+    }
+
+    if sprite_count.flicker {
+        cycle.frame += 1;
+        if cycle.frame >= sprite_count.flicker_interval.max(1) {
+            cycle.frame = 0;
+            if let Some(first) = order.into_iter().find(|entity| dropped.contains(entity)) {
+                cycle.start = Some(first);
+            }
+        }
+    }
+}
+
+/// Drops the sprites the limiter culled right before they are drawn. This way the limiter never has to touch Visibility
+/// and anything hidden by gameplay code stays hidden. Children of a culled sprite are dropped with it
+pub fn remove_culled_sprites(
+    mut extracted_sprites: ResMut<ExtractedSprites>,
+    culled_query: Extract<Query<(), With<LimitCulled>>>,
+    parent_query: Extract<Query<&Parent>>,
+) {
+    if culled_query.is_empty() {
+        return;
+    }
+    extracted_sprites.sprites.retain(|sprite| {
+        let mut entity = Some(sprite.entity);
+        while let Some(current) = entity {
+            if culled_query.contains(current) {
+                return false;
+            }
+            entity = parent_query.get(current).ok().map(Parent::get);
+        }
+        true
+    });
+}
+
+/// Reports sprites that are bigger than the max sprite size and crops them if asked to. Sprites are only checked again
+/// when they or their image change and each sprite is only reported again when its image or size is different from
+/// the last report, so animating an oversized sprite doesn't report it every frame. Rotated sprites are checked by
+/// the image they were rotated from and that is what gets cropped, the rotated copy is then made from the cropped part
+#[allow(clippy::type_complexity)]
+pub fn check_sprite_sizes(
+    mut sprite_query: Query<
+        (
+            Entity,
+            &mut Sprite,
+            Ref<Handle<Image>>,
+            Option<&mut PixelRotationSource>,
+        ),
+        With<PixelSprite>,
+    >,
+    size_limit: Res<SpriteSizeLimit>,
+    images: Res<Assets<Image>>,
+    mut image_events: EventReader<AssetEvent<Image>>,
+    mut violations: EventWriter<SpriteSizeViolation>,
+    mut reported: Local<HashMap<Entity, (HandleId, Vec2)>>,
+) {
+    reported.retain(|entity, _| sprite_query.contains(*entity));
+    let loaded = image_events
+        .iter()
+        .filter_map(|event| match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => Some(handle.id()),
+            AssetEvent::Removed { .. } => None,
+        })
+        .collect::<HashSet<_>>();
+    let max = size_limit.max.as_vec2();
+    for (entity, mut sprite, image, rotation) in sprite_query.iter_mut() {
+        let rotation_changed = rotation.as_ref().is_some_and(|source| source.is_changed());
+        if !sprite.is_changed()
+            && !image.is_changed()
+            && !rotation_changed
+            && !loaded.contains(&image.id())
+        {
+            continue;
+        }
+        let (image_id, rect, custom_size) = match rotation.as_deref() {
+            Some(source) => (source.original.id(), source.rect, source.custom_size),
+            None => (image.id(), sprite.rect, sprite.custom_size),
+        };
+        let image_size = images
+            .get(&Handle::weak(image_id))
+            .map(|image| image.size());
+        let Some(size) = custom_size
+            .or_else(|| rect.map(|rect| rect.size()))
+            .or(image_size)
+        else {
+            continue;
+        };
+        if !size.cmpgt(max).any() {
+            continue;
+        }
+        if reported.insert(entity, (image_id, size)) != Some((image_id, size)) {
+            warn!(
+                "Sprite {:?} is {}x{} which is bigger than the max sprite size of {}x{}",
+                entity, size.x, size.y, size_limit.max.x, size_limit.max.y
+            );
+            violations.send(SpriteSizeViolation {
+                entity,
+                size,
+                max: size_limit.max,
+            });
+        }
+        if size_limit.crop {
+            let cropped_size = custom_size.map(|custom_size| custom_size.min(max));
+            // A custom size stretches the image so the part of it that stays on screen has to be worked out in image pixels
+            let cropped_rect = rect
+                .map(|rect| rect.size())
+                .or(image_size)
+                .map(|source_size| {
+                    let min = rect.map_or(Vec2::ZERO, |rect| rect.min);
+                    let kept = match custom_size {
+                        Some(custom_size) => source_size * (custom_size.min(max) / custom_size),
+                        None => source_size.min(max),
+                    };
+                    Rect::from_corners(min, min + kept)
+                })
+                .or(rect);
+            match rotation {
+                Some(mut source) => {
+                    source.custom_size = cropped_size;
+                    source.rect = cropped_rect;
+                }
+                None => {
+                    sprite.custom_size = cropped_size;
+                    sprite.rect = cropped_rect;
+                }
+            }
+        }
+    }
+}
+
+/// An image together with the pixel rect of it a sprite shows, None being the whole image
+pub type ImageRegion = (HandleId, Option<[u32; 4]>);
+
+pub fn image_region(image: HandleId, rect: Option<Rect>) -> ImageRegion {
+    (image, rect.map(pixel_rect))
+}
+
+/// The corners of a rect as whole pixels, min x, min y, max x, max y
+pub fn pixel_rect(rect: Rect) -> [u32; 4] {
+    [rect.min.x, rect.min.y, rect.max.x, rect.max.y].map(|value| value.max(0.) as u32)
+}
+
+/// Counts the distinct colors of every pixel inside rect that isn't fully transparent
+/// Returns None for formats that aren't 8 bit rgba
+pub fn count_opaque_colors(image: &Image, rect: Option<Rect>) -> Option<u32> {
+    match image.texture_descriptor.format {
+        TextureFormat::Rgba8Unorm
+        | TextureFormat::Rgba8UnormSrgb
+        | TextureFormat::Bgra8Unorm
+        | TextureFormat::Bgra8UnormSrgb => (),
+        _ => return None,
+    }
+    let width = image.texture_descriptor.size.width as usize;
+    let height = image.texture_descriptor.size.height as usize;
+    let [min_x, min_y, max_x, max_y] = rect
+        .map(|rect| pixel_rect(rect).map(|value| value as usize))
+        .unwrap_or([0, 0, width, height]);
+    let mut colors = HashSet::new();
+    for y in min_y..max_y.min(height) {
+        for x in min_x..max_x.min(width) {
+            let start = (y * width + x) * 4;
+            if let Some(pixel) = image.data.get(start..start + 4) {
+                if pixel[3] != 0 {
+                    colors.insert([pixel[0], pixel[1], pixel[2]]);
+                }
+            }
+        }
+    }
+    Some(colors.len() as u32)
+}
+
+/// Checks the part of each image a sprite shows once it's loaded and again whenever it changes
+/// Only reports when the count of a region changes so editing an image doesn't repeat the same warning
+pub fn check_sprite_colors(
+    sprite_query: Query<(&Handle<Image>, &Sprite), With<PixelSprite>>,
+    mut color_limit: ResMut<SpriteColorLimit>,
+    images: Res<Assets<Image>>,
+    mut image_events: EventReader<AssetEvent<Image>>,
+    mut violations: EventWriter<SpriteColorViolation>,
+    mut counts: Local<HashMap<ImageRegion, u32>>,
+    mut checked: Local<HashSet<ImageRegion>>,
+) {
+    for event in image_events.iter() {
+        match event {
+            AssetEvent::Modified { handle } => {
+                checked.retain(|(image, _)| *image != handle.id());
+            }
+            AssetEvent::Removed { handle } => {
+                checked.retain(|(image, _)| *image != handle.id());
+                counts.retain(|(image, _), _| *image != handle.id());
+                color_limit
+                    .rejected
+                    .retain(|(image, _)| *image != handle.id());
             }
+            AssetEvent::Created { .. } => (),
         }
     }
+    for (handle, sprite) in sprite_query.iter() {
+        let region = image_region(handle.id(), sprite.rect);
+        if checked.contains(&region) {
+            continue;
+        }
+        let Some(image) = images.get(handle) else {
+            continue;
+        };
+        checked.insert(region);
+        let Some(colors) = count_opaque_colors(image, sprite.rect) else {
+            continue;
+        };
+        if counts.insert(region, colors) == Some(colors) {
+            continue;
+        }
+        if colors > color_limit.max {
+            warn!(
+                "Image {:?} has {} colors in {:?} which is more than the limit of {}",
+                handle.id(),
+                colors,
+                sprite.rect,
+                color_limit.max
+            );
+            violations.send(SpriteColorViolation {
+                image: handle.clone_weak(),
+                rect: sprite.rect,
+                colors,
+                max: color_limit.max,
+            });
+            color_limit.rejected.insert(region);
+        } else {
+            color_limit.rejected.remove(&region);
+        }
+    }
+}
+
+/// Counts the opaque colors of an image that aren't in the palette. Returns None for images that aren't 8 bit rgba
+pub fn count_foreign_colors(image: &Image, palette: &PixelPalette) -> Option<u32> {
+    let palette = palette
+        .colors
+        .iter()
+        .map(|color| {
+            let [r, g, b, _] = rgba_u8(*color);
+            [r, g, b]
+        })
+        .collect::<HashSet<_>>();
+    let colors = rgba_pixels(image)?
+        .into_iter()
+        .filter(|[r, g, b, a]| *a != 0 && !palette.contains(&[*r, *g, *b]))
+        .map(|[r, g, b, _]| [r, g, b])
+        .collect::<HashSet<_>>();
+    Some(colors.len() as u32)
+}
+
+/// Stops the app with a clear message when palettes are checked without the palette plugin that provides them
+pub fn require_palette_plugin(palettes: Option<Res<Assets<PixelPalette>>>) {
+    if palettes.is_none() {
+        panic!("PixelLimPlugin::check_palettes needs the PixelPalettePlugin to be added as well");
+    }
+}
+
+/// Checks each image against the palette of the layer its sprite is on. Each image and palette pair is only counted
+/// again when one of them changes, and every sprite using a pair is reported once until its count changes
+#[allow(clippy::type_complexity)]
+pub fn check_sprite_palettes(
+    sprite_query: Query<(Entity, &Handle<Image>), With<PixelSprite>>,
+    sprite_palettes: SpritePalettes,
+    images: Res<Assets<Image>>,
+    mut events: (
+        EventReader<AssetEvent<Image>>,
+        EventReader<AssetEvent<PixelPalette>>,
+    ),
+    mut violations: EventWriter<SpritePaletteViolation>,
+    mut counts: Local<HashMap<(HandleId, HandleId), Option<u32>>>,
+    mut reported: Local<HashMap<Entity, ((HandleId, HandleId), u32)>>,
+) {
+    let (image_events, palette_events) = &mut events;
+    for event in image_events.iter() {
+        if let AssetEvent::Modified { handle } | AssetEvent::Removed { handle } = event {
+            counts.retain(|(image, _), _| *image != handle.id());
+        }
+    }
+    for event in palette_events.iter() {
+        if let AssetEvent::Modified { handle } | AssetEvent::Removed { handle } = event {
+            counts.retain(|(_, palette), _| *palette != handle.id());
+        }
+    }
+    reported.retain(|entity, _| sprite_query.contains(*entity));
+    for (entity, image_handle) in sprite_query.iter() {
+        let palette_handle = sprite_palettes.handle(entity);
+        let key = (image_handle.id(), palette_handle.id());
+        let colors = match counts.get(&key) {
+            Some(colors) => *colors,
+            None => {
+                let (Some(image), Some(palette)) = (
+                    images.get(image_handle),
+                    sprite_palettes.palettes.get(palette_handle),
+                ) else {
+                    continue;
+                };
+                let colors = count_foreign_colors(image, palette);
+                counts.insert(key, colors);
+                colors
+            }
+        };
+        match colors {
+            Some(colors) if colors > 0 => {
+                if reported.get(&entity) == Some(&(key, colors)) {
+                    continue;
+                }
+                warn!(
+                    "Sprite {:?} uses image {:?} which has {} colors that aren't in the palette {:?} of its layer",
+                    entity,
+                    image_handle.id(),
+                    colors,
+                    palette_handle.id()
+                );
+                violations.send(SpritePaletteViolation {
+                    entity,
+                    image: image_handle.clone_weak(),
+                    palette: palette_handle.clone_weak(),
+                    colors,
+                });
+                reported.insert(entity, (key, colors));
+            }
+            _ => {
+                reported.remove(&entity);
+            }
+        }
+    }
+}
+
+/// Drops sprites using an image with too many colors when the color limit is strict
+pub fn remove_rejected_sprites(
+    mut extracted_sprites: ResMut<ExtractedSprites>,
+    color_limit: Extract<Res<SpriteColorLimit>>,
+) {
+    if color_limit.strict {
+        extracted_sprites.sprites.retain(|sprite| {
+            !color_limit
+                .rejected
+                .contains(&image_region(sprite.image_handle_id, sprite.rect))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::schedule::ExecutorKind;
+    use bevy::render::render_resource::{Extent3d, TextureDimension};
+    use bevy::render::MainWorld;
+    use bevy::sprite::ExtractedSprite;
+
+    use super::*;
+
+    #[test]
+    fn colors_are_counted_inside_the_rect() {
+        // Left half has red and green, right half blue and a transparent pixel
+        let data = [
+            [255, 0, 0, 255],
+            [0, 0, 255, 255],
+            [0, 255, 0, 255],
+            [255, 255, 255, 0],
+        ]
+        .concat();
+        let image = Image::new(
+            Extent3d {
+                width: 2,
+                height: 2,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8UnormSrgb,
+        );
+        assert_eq!(count_opaque_colors(&image, None), Some(3));
+        let left = Rect::new(0., 0., 1., 2.);
+        assert_eq!(count_opaque_colors(&image, Some(left)), Some(2));
+        let right = Rect::new(1., 0., 2., 2.);
+        assert_eq!(count_opaque_colors(&image, Some(right)), Some(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "needs the PixelPalettePlugin")]
+    fn checking_palettes_needs_the_palette_plugin() {
+        let mut schedule = Schedule::new();
+        schedule.set_executor_kind(ExecutorKind::SingleThreaded);
+        schedule.add_system(require_palette_plugin);
+        schedule.run(&mut World::new());
+    }
+
+    fn limiter_app(count: u32, scanline_count: u32, flicker: bool, flicker_interval: u32) -> App {
+        let mut app = App::new();
+        app.insert_resource(SpriteCount {
+            count,
+            scanline_count,
+            flicker,
+            flicker_interval,
+        })
+        .add_plugin(TaskPoolPlugin::default())
+        .add_plugin(AssetPlugin::default())
+        .add_asset::<Image>()
+        .add_system(sprite_count_limiter);
+        app
+    }
+
+    /// An 8x8 sprite centered on `position`
+    fn spawn_sprite(app: &mut App, position: Vec2) -> Entity {
+        app.world
+            .spawn((
+                PixelSprite,
+                Sprite {
+                    custom_size: Some(Vec2::splat(8.0)),
+                    ..default()
+                },
+                GlobalTransform::from_translation(position.extend(0.0)),
+            ))
+            .id()
+    }
+
+    fn culled(app: &App, entities: &[Entity]) -> Vec<bool> {
+        entities
+            .iter()
+            .map(|entity| app.world.get::<LimitCulled>(*entity).is_some())
+            .collect()
+    }
+
+    #[test]
+    fn crowded_scanlines_drop_only_their_own_sprites() {
+        let mut app = limiter_app(0, 2, false, 1);
+        let row = [
+            spawn_sprite(&mut app, Vec2::new(0.0, 0.0)),
+            spawn_sprite(&mut app, Vec2::new(20.0, 0.0)),
+            // Only shares the top half of the row but that is enough to go over
+            spawn_sprite(&mut app, Vec2::new(40.0, 4.0)),
+        ];
+        let other_row = [
+            spawn_sprite(&mut app, Vec2::new(0.0, 100.0)),
+            spawn_sprite(&mut app, Vec2::new(20.0, 100.0)),
+        ];
+        app.update();
+        assert_eq!(culled(&app, &row), [false, false, true]);
+        assert_eq!(culled(&app, &other_row), [false, false]);
+    }
+
+    fn shown(app: &App, entities: &[Entity]) -> Vec<usize> {
+        culled(app, entities)
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, culled)| (!culled).then_some(index))
+            .collect()
+    }
+
+    #[test]
+    fn sprites_over_the_budget_are_culled() {
+        let mut app = limiter_app(2, 0, false, 1);
+        let sprites = (0..4)
+            .map(|index| spawn_sprite(&mut app, Vec2::new(index as f32 * 20.0, 0.0)))
+            .collect::<Vec<_>>();
+        for _ in 0..3 {
+            app.update();
+            assert_eq!(shown(&app, &sprites), [0, 1]);
+        }
+        // A freed up slot goes to the next sprite in line
+        app.world.despawn(sprites[0]);
+        app.update();
+        assert_eq!(shown(&app, &sprites[1..]), [0, 1]);
+    }
+
+    #[test]
+    fn flicker_gives_every_sprite_a_turn() {
+        let mut app = limiter_app(2, 0, true, 1);
+        let sprites = (0..5)
+            .map(|index| spawn_sprite(&mut app, Vec2::new(index as f32 * 20.0, 0.0)))
+            .collect::<Vec<_>>();
+        let mut steps = Vec::new();
+        for _ in 0..5 {
+            app.update();
+            steps.push(shown(&app, &sprites));
+        }
+        assert_eq!(
+            steps,
+            [vec![0, 1], vec![2, 3], vec![0, 4], vec![1, 2], vec![3, 4]]
+        );
+    }
+
+    #[test]
+    fn flicker_keeps_its_place_when_sprites_leave() {
+        let mut app = limiter_app(2, 0, true, 1);
+        let sprites = (0..5)
+            .map(|index| spawn_sprite(&mut app, Vec2::new(index as f32 * 20.0, 0.0)))
+            .collect::<Vec<_>>();
+        app.update();
+        assert_eq!(shown(&app, &sprites), [0, 1]);
+        // Hiding a sprite that was just shown changes the count, sprite 2 is still next in line though
+        app.world
+            .entity_mut(sprites[0])
+            .insert(ComputedVisibility::default());
+        app.update();
+        assert_eq!(shown(&app, &sprites[1..]), [1, 2]);
+        app.update();
+        assert_eq!(shown(&app, &sprites[1..]), [0, 3]);
+    }
+
+    #[test]
+    fn flicker_interval_holds_each_step() {
+        let mut app = limiter_app(1, 0, true, 3);
+        let sprites = (0..2)
+            .map(|index| spawn_sprite(&mut app, Vec2::new(index as f32 * 20.0, 0.0)))
+            .collect::<Vec<_>>();
+        let mut steps = Vec::new();
+        for _ in 0..7 {
+            app.update();
+            steps.push(shown(&app, &sprites));
+        }
+        assert_eq!(
+            steps,
+            [
+                vec![0],
+                vec![0],
+                vec![0],
+                vec![1],
+                vec![1],
+                vec![1],
+                vec![0]
+            ]
+        );
+    }
+
+    #[test]
+    fn higher_priorities_get_a_slot_first() {
+        let mut app = limiter_app(2, 0, false, 1);
+        let sprites = [None, Some(5), Some(1), Some(-1)].map(|priority| {
+            let sprite = spawn_sprite(&mut app, Vec2::ZERO);
+            if let Some(priority) = priority {
+                app.world
+                    .entity_mut(sprite)
+                    .insert(SpritePriority(priority));
+            }
+            sprite
+        });
+        app.update();
+        assert_eq!(shown(&app, &sprites), [1, 2]);
+        // Without priorities the lowest entities win
+        app.world.entity_mut(sprites[1]).remove::<SpritePriority>();
+        app.world.entity_mut(sprites[2]).remove::<SpritePriority>();
+        app.update();
+        assert_eq!(shown(&app, &sprites), [0, 1]);
+    }
+
+    #[test]
+    fn always_sprites_are_never_dropped_but_use_the_budget() {
+        let mut app = limiter_app(2, 1, true, 1);
+        let normal = spawn_sprite(&mut app, Vec2::ZERO);
+        let always = [0.0, 20.0, 40.0].map(|x| {
+            let sprite = spawn_sprite(&mut app, Vec2::new(x, 0.0));
+            app.world.entity_mut(sprite).insert(SpritePriority::ALWAYS);
+            sprite
+        });
+        for _ in 0..3 {
+            app.update();
+            assert_eq!(culled(&app, &always), [false; 3]);
+            assert_eq!(culled(&app, &[normal]), [true]);
+        }
+        // Off the crowded row it still doesn't get a slot as the always sprites took the whole count
+        app.world
+            .entity_mut(normal)
+            .insert(GlobalTransform::from_xyz(0.0, 100.0, 0.0));
+        app.update();
+        assert_eq!(culled(&app, &[normal]), [true]);
+    }
+
+    #[test]
+    fn culled_sprites_take_their_children_with_them() {
+        let mut main = World::new();
+        let culled = main.spawn(LimitCulled).id();
+        let child = main.spawn_empty().id();
+        let grandchild = main.spawn_empty().id();
+        let other = main.spawn_empty().id();
+        main.entity_mut(culled).push_children(&[child]);
+        main.entity_mut(child).push_children(&[grandchild]);
+
+        let mut world = World::new();
+        let mut main_world = MainWorld::default();
+        *main_world = main;
+        world.insert_resource(main_world);
+        world.insert_resource(ExtractedSprites {
+            sprites: [culled, child, grandchild, other]
+                .map(|entity| ExtractedSprite {
+                    entity,
+                    transform: GlobalTransform::default(),
+                    color: Color::WHITE,
+                    rect: None,
+                    custom_size: None,
+                    image_handle_id: HandleId::default::<Image>(),
+                    flip_x: false,
+                    flip_y: false,
+                    anchor: Vec2::ZERO,
+                })
+                .to_vec(),
+        });
+        let mut schedule = Schedule::new();
+        schedule.add_system(remove_culled_sprites);
+        schedule.run(&mut world);
+        let left = world
+            .resource::<ExtractedSprites>()
+            .sprites
+            .iter()
+            .map(|sprite| sprite.entity)
+            .collect::<Vec<_>>();
+        assert_eq!(left, [other]);
+    }
+
+    #[test]
+    fn culled_marker_is_removed_once_there_is_room() {
+        let mut app = limiter_app(1, 0, false, 1);
+        let first = spawn_sprite(&mut app, Vec2::ZERO);
+        let second = spawn_sprite(&mut app, Vec2::ZERO);
+        app.update();
+        assert_eq!(culled(&app, &[first, second]), [false, true]);
+        app.world
+            .entity_mut(first)
+            .insert(ComputedVisibility::default());
+        app.update();
+        assert_eq!(culled(&app, &[second]), [false]);
+    }
+
+    fn size_app(max: UVec2, crop: bool) -> App {
+        let mut app = App::new();
+        app.insert_resource(SpriteSizeLimit { max, crop })
+            .add_plugin(TaskPoolPlugin::default())
+            .add_plugin(AssetPlugin::default())
+            .add_asset::<Image>()
+            .add_event::<SpriteSizeViolation>()
+            .add_system(check_sprite_sizes);
+        app
+    }
+
+    fn spawn_image_sprite(app: &mut App, size: UVec2, sprite: Sprite) -> Entity {
+        let image = Image::new_fill(
+            Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[255; 4],
+            TextureFormat::Rgba8UnormSrgb,
+        );
+        let image = app.world.resource_mut::<Assets<Image>>().add(image);
+        app.world.spawn((PixelSprite, sprite, image)).id()
+    }
+
+    fn size_violations(app: &mut App) -> Vec<(Entity, Vec2)> {
+        app.world
+            .resource_mut::<Events<SpriteSizeViolation>>()
+            .drain()
+            .map(|violation| (violation.entity, violation.size))
+            .collect()
+    }
+
+    #[test]
+    fn oversized_sprites_are_reported_once() {
+        let mut app = size_app(UVec2::splat(16), false);
+        let small = spawn_image_sprite(&mut app, UVec2::splat(16), Sprite::default());
+        let big = spawn_image_sprite(&mut app, UVec2::new(32, 8), Sprite::default());
+        app.update();
+        assert_eq!(size_violations(&mut app), [(big, Vec2::new(32.0, 8.0))]);
+        app.world.get_mut::<Sprite>(big).unwrap().color = Color::RED;
+        app.update();
+        assert_eq!(size_violations(&mut app), []);
+        // A custom size counts instead of the image
+        app.world.get_mut::<Sprite>(small).unwrap().custom_size = Some(Vec2::new(8.0, 24.0));
+        app.update();
+        assert_eq!(size_violations(&mut app), [(small, Vec2::new(8.0, 24.0))]);
+    }
+
+    #[test]
+    fn cropping_keeps_the_top_left_of_the_image() {
+        let mut app = size_app(UVec2::splat(16), true);
+        let sprite = spawn_image_sprite(&mut app, UVec2::new(32, 8), Sprite::default());
+        let with_rect = spawn_image_sprite(
+            &mut app,
+            UVec2::splat(64),
+            Sprite {
+                rect: Some(Rect::new(8.0, 8.0, 40.0, 16.0)),
+                ..default()
+            },
+        );
+        app.update();
+        let sprite = app.world.get::<Sprite>(sprite).unwrap();
+        assert_eq!(sprite.rect, Some(Rect::new(0.0, 0.0, 16.0, 8.0)));
+        assert_eq!(sprite.custom_size, None);
+        let with_rect = app.world.get::<Sprite>(with_rect).unwrap();
+        assert_eq!(with_rect.rect, Some(Rect::new(8.0, 8.0, 24.0, 16.0)));
+    }
+
+    #[test]
+    fn cropping_a_stretched_sprite_keeps_its_scale() {
+        let mut app = size_app(UVec2::splat(16), true);
+        // Drawn 4 times bigger than the image, so only the top left quarter of it fits in 16x16
+        let stretched = spawn_image_sprite(
+            &mut app,
+            UVec2::splat(8),
+            Sprite {
+                custom_size: Some(Vec2::splat(32.0)),
+                ..default()
+            },
+        );
+        app.update();
+        let sprite = app.world.get::<Sprite>(stretched).unwrap();
+        assert_eq!(sprite.custom_size, Some(Vec2::splat(16.0)));
+        assert_eq!(sprite.rect, Some(Rect::new(0.0, 0.0, 4.0, 4.0)));
+        // Cropped sprites fit so they aren't cropped or reported again
+        app.update();
+        assert_eq!(size_violations(&mut app).len(), 1);
+        let sprite = app.world.get::<Sprite>(stretched).unwrap();
+        assert_eq!(sprite.rect, Some(Rect::new(0.0, 0.0, 4.0, 4.0)));
+    }
 }
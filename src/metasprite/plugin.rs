@@ -0,0 +1,12 @@
+use bevy::prelude::*;
+
+use super::system::build_metasprites;
+
+/// Builds every [`super::system::Metasprite`] out of child sprites, one for each hardware tile
+pub struct PixelMetaspritePlugin;
+
+impl Plugin for PixelMetaspritePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(build_metasprites);
+    }
+}
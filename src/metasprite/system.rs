@@ -0,0 +1,335 @@
+use bevy::{
+    prelude::*,
+    sprite::Anchor,
+    utils::{HashMap, HashSet},
+};
+
+use crate::{layers::system::PixelLayerOffset, limit::plugin::SpritePriority, plugin::PixelSprite};
+
+/// The size of a single hardware tile
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TileSize {
+    /// 8x8 tiles
+    #[default]
+    Small,
+    /// 8x16 tiles
+    Tall,
+}
+
+impl TileSize {
+    pub fn size(&self) -> UVec2 {
+        match self {
+            TileSize::Small => UVec2::new(8, 8),
+            TileSize::Tall => UVec2::new(8, 16),
+        }
+    }
+}
+
+/// One tile of a metasprite. `tile` is the column and row of the tile in the sprite sheet and `offset` is where the
+/// bottom left corner of the tile goes relative to the metasprite in pixels
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct MetaspriteTile {
+    pub tile: UVec2,
+    pub offset: IVec2,
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+impl MetaspriteTile {
+    pub fn new(tile: UVec2, offset: IVec2) -> Self {
+        Self {
+            tile,
+            offset,
+            ..Default::default()
+        }
+    }
+
+    pub fn flipped(mut self, flip_x: bool, flip_y: bool) -> Self {
+        self.flip_x = flip_x;
+        self.flip_y = flip_y;
+        self
+    }
+}
+
+/// A sprite built out of multiple hardware tiles like on older consoles. Each tile is spawned as a child sprite so
+/// with a PixelSprite each tile counts towards the sprite limit on its own, the metasprite itself doesn't count.
+/// PixelSprite and SpritePriority are copied to the tiles whenever they are built. Give the metasprite a PixelLayer and
+/// the tiles are drawn in it and y sorted by the metasprite's position
+#[derive(Component, Debug, Default, Clone)]
+pub struct Metasprite {
+    pub sheet: Handle<Image>,
+    pub tile_size: TileSize,
+    pub tiles: Vec<MetaspriteTile>,
+}
+
+impl Metasprite {
+    pub fn new(sheet: Handle<Image>, tile_size: TileSize, tiles: Vec<MetaspriteTile>) -> Self {
+        Self {
+            sheet,
+            tile_size,
+            tiles,
+        }
+    }
+
+    /// A metasprite made of a rectangle of tiles that are laid out the same way in the sprite sheet. `first` is the top left tile
+    pub fn grid(
+        sheet: Handle<Image>,
+        tile_size: TileSize,
+        first: UVec2,
+        columns: u32,
+        rows: u32,
+    ) -> Self {
+        let size = tile_size.size().as_ivec2();
+        let tiles = (0..rows)
+            .flat_map(|row| {
+                (0..columns).map(move |column| {
+                    MetaspriteTile::new(
+                        first + UVec2::new(column, row),
+                        IVec2::new(column as i32 * size.x, (rows - row - 1) as i32 * size.y),
+                    )
+                })
+            })
+            .collect();
+        Self::new(sheet, tile_size, tiles)
+    }
+}
+
+/// Marks the child sprites spawned for a metasprite
+#[derive(Component, Copy, Clone)]
+pub struct MetaspriteTileTag;
+
+/// Respawns the tiles of every metasprite that was added or changed, or whose sheet was loaded or resized. Tiles that
+/// fall outside of the sheet are left out. Edits to the pixels of a sheet show up on the tiles by themselves so those
+/// don't respawn anything
+#[allow(clippy::type_complexity)]
+pub fn build_metasprites(
+    mut commands: Commands,
+    metasprite_query: Query<(
+        Entity,
+        Ref<Metasprite>,
+        Option<&Children>,
+        Option<&PixelSprite>,
+        Option<&SpritePriority>,
+    )>,
+    tile_query: Query<(), With<MetaspriteTileTag>>,
+    images: Res<Assets<Image>>,
+    mut image_events: EventReader<AssetEvent<Image>>,
+    mut built_sizes: Local<HashMap<Entity, Option<UVec2>>>,
+) {
+    built_sizes.retain(|entity, _| metasprite_query.contains(*entity));
+    let changed_sheets = image_events
+        .iter()
+        .filter_map(|event| match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => Some(handle.id()),
+            AssetEvent::Removed { .. } => None,
+        })
+        .collect::<HashSet<_>>();
+    for (entity, metasprite, children, pixel_sprite, priority) in metasprite_query.iter() {
+        if !metasprite.is_changed() && !changed_sheets.contains(&metasprite.sheet.id()) {
+            continue;
+        }
+        let sheet_size = images
+            .get(&metasprite.sheet)
+            .map(|image| image.size().as_uvec2());
+        if built_sizes.insert(entity, sheet_size) == Some(sheet_size) && !metasprite.is_changed() {
+            continue;
+        }
+        if let Some(children) = children {
+            for child in children.iter().filter(|child| tile_query.contains(**child)) {
+                commands.entity(*child).despawn_recursive();
+            }
+        }
+
+        let size = metasprite.tile_size.size();
+        commands.entity(entity).with_children(|parent| {
+            for tile in metasprite.tiles.iter() {
+                if sheet_size
+                    .is_some_and(|sheet_size| ((tile.tile + 1) * size).cmpgt(sheet_size).any())
+                {
+                    warn!(
+                        "Metasprite tile {:?} is outside of its sheet {:?}",
+                        tile.tile,
+                        metasprite.sheet.id()
+                    );
+                    continue;
+                }
+                let min = (tile.tile * size).as_vec2();
+                let mut tile_entity = parent.spawn((
+                    SpriteBundle {
+                        sprite: Sprite {
+                            rect: Some(Rect::from_corners(min, min + size.as_vec2())),
+                            flip_x: tile.flip_x,
+                            flip_y: tile.flip_y,
+                            anchor: Anchor::BottomLeft,
+                            ..default()
+                        },
+                        texture: metasprite.sheet.clone(),
+                        transform: Transform::from_translation(tile.offset.as_vec2().extend(0.0)),
+                        ..default()
+                    },
+                    PixelLayerOffset(0),
+                    MetaspriteTileTag,
+                ));
+                if let Some(pixel_sprite) = pixel_sprite {
+                    tile_entity.insert(*pixel_sprite);
+                }
+                if let Some(priority) = priority {
+                    tile_entity.insert(*priority);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+    use super::*;
+
+    fn sheet(width: u32, height: u32) -> Image {
+        Image::new_fill(
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[0, 0, 0, 255],
+            TextureFormat::Rgba8UnormSrgb,
+        )
+    }
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugin(TaskPoolPlugin::default())
+            .add_plugin(AssetPlugin::default())
+            .add_asset::<Image>()
+            .add_system(build_metasprites);
+        app
+    }
+
+    fn tiles(app: &App, metasprite: Entity) -> Vec<(Entity, Sprite, Vec3)> {
+        app.world
+            .get::<Children>(metasprite)
+            .map(|children| children.iter().copied().collect::<Vec<_>>())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|tile| {
+                let sprite = app.world.get::<Sprite>(tile).unwrap().clone();
+                (
+                    tile,
+                    sprite,
+                    app.world.get::<Transform>(tile).unwrap().translation,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn grid_tiles_are_laid_out_like_the_sheet() {
+        let mut app = app();
+        let handle = app.world.resource_mut::<Assets<Image>>().add(sheet(32, 32));
+        let metasprite = app
+            .world
+            .spawn(Metasprite::grid(
+                handle,
+                TileSize::Small,
+                UVec2::new(1, 2),
+                2,
+                2,
+            ))
+            .id();
+        app.update();
+        let tiles = tiles(&app, metasprite);
+        let layout = tiles
+            .iter()
+            .map(|(_, sprite, translation)| (sprite.rect.unwrap().min, translation.truncate()))
+            .collect::<Vec<_>>();
+        // The top row of the sheet ends up on top, which is the higher y in the world
+        assert_eq!(
+            layout,
+            [
+                (Vec2::new(8.0, 16.0), Vec2::new(0.0, 8.0)),
+                (Vec2::new(16.0, 16.0), Vec2::new(8.0, 8.0)),
+                (Vec2::new(8.0, 24.0), Vec2::new(0.0, 0.0)),
+                (Vec2::new(16.0, 24.0), Vec2::new(8.0, 0.0)),
+            ]
+        );
+        assert!(tiles
+            .iter()
+            .all(|(_, sprite, _)| sprite.rect.unwrap().size() == Vec2::splat(8.0)));
+    }
+
+    #[test]
+    fn tiles_are_flipped_and_kept_inside_the_sheet() {
+        let mut app = app();
+        let handle = app.world.resource_mut::<Assets<Image>>().add(sheet(16, 16));
+        let metasprite = app
+            .world
+            .spawn(Metasprite::new(
+                handle,
+                TileSize::Tall,
+                vec![
+                    MetaspriteTile::new(UVec2::new(1, 0), IVec2::ZERO).flipped(true, false),
+                    MetaspriteTile::new(UVec2::new(0, 0), IVec2::new(-8, 0)).flipped(false, true),
+                    // A tall tile in the second row would need a sheet 32 pixels high
+                    MetaspriteTile::new(UVec2::new(0, 1), IVec2::ZERO),
+                ],
+            ))
+            .id();
+        app.update();
+        let flips = tiles(&app, metasprite)
+            .into_iter()
+            .map(|(_, sprite, _)| (sprite.flip_x, sprite.flip_y, sprite.rect.unwrap().size()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            flips,
+            [
+                (true, false, Vec2::new(8.0, 16.0)),
+                (false, true, Vec2::new(8.0, 16.0))
+            ]
+        );
+    }
+
+    #[test]
+    fn tiles_are_only_respawned_when_the_sheet_is_resized() {
+        let mut app = app();
+        let handle = app.world.resource_mut::<Assets<Image>>().add(sheet(8, 8));
+        let metasprite = app
+            .world
+            .spawn(Metasprite::grid(
+                handle.clone(),
+                TileSize::Small,
+                UVec2::ZERO,
+                2,
+                1,
+            ))
+            .id();
+        app.update();
+        app.update();
+        let first = tiles(&app, metasprite);
+        assert_eq!(first.len(), 1);
+
+        // Painting the sheet sends Modified but the same tiles still fit
+        app.world
+            .resource_mut::<Assets<Image>>()
+            .get_mut(&handle)
+            .unwrap()
+            .data[0] = 255;
+        app.update();
+        app.update();
+        assert_eq!(tiles(&app, metasprite)[0].0, first[0].0);
+
+        // Growing it makes room for the second tile
+        *app.world
+            .resource_mut::<Assets<Image>>()
+            .get_mut(&handle)
+            .unwrap() = sheet(16, 8);
+        app.update();
+        app.update();
+        let grown = tiles(&app, metasprite);
+        assert_eq!(grown.len(), 2);
+        assert!(grown.iter().all(|(tile, ..)| *tile != first[0].0));
+    }
+}
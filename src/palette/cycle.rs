@@ -0,0 +1,85 @@
+use std::ops::Range;
+
+/// Which way the colors move through a cycle
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum CycleDirection {
+    /// Each color moves up one index per step
+    #[default]
+    Forward,
+    /// Each color moves down one index per step
+    Backward,
+}
+
+/// Rotates the colors in a range of palette indices over time, like the classic water and lava effects. Anything
+/// drawn with those indices animates without touching the sprites. How far a cycle has gotten is kept in
+/// [`super::plugin::PaletteDisplays`] so the palette itself never changes. A range running past the end of the
+/// palette is cut off at the last color
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PaletteCycle {
+    pub range: Range<usize>,
+    /// Steps per second. Colors only ever move by whole indices
+    pub speed: f32,
+    pub direction: CycleDirection,
+}
+
+impl PaletteCycle {
+    pub fn new(range: Range<usize>, speed: f32, direction: CycleDirection) -> Self {
+        Self {
+            range,
+            speed,
+            direction,
+        }
+    }
+
+    /// The indices that cycle in a palette of `len` colors
+    pub fn indices(&self, len: usize) -> Range<usize> {
+        self.range.start.min(len)..self.range.end.min(len)
+    }
+
+    /// The index whose color is shown at `index` after `step` steps in a palette of `len` colors, or None if the index
+    /// isn't in the cycle
+    pub fn source(&self, index: usize, step: usize, len: usize) -> Option<usize> {
+        let range = self.indices(len);
+        if !range.contains(&index) {
+            return None;
+        }
+        let len = range.len();
+        let step = step % len;
+        let offset = index - range.start;
+        let offset = match self.direction {
+            CycleDirection::Forward => (offset + len - step) % len,
+            CycleDirection::Backward => (offset + step) % len,
+        };
+        Some(range.start + offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_move_through_the_range() {
+        let cycle = PaletteCycle::new(1..4, 1.0, CycleDirection::Forward);
+        let shown = |step| {
+            (0..5)
+                .map(|index| cycle.source(index, step, 5))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(shown(0), [None, Some(1), Some(2), Some(3), None]);
+        assert_eq!(shown(1), [None, Some(3), Some(1), Some(2), None]);
+        assert_eq!(shown(3), shown(0));
+
+        let cycle = PaletteCycle::new(1..4, 1.0, CycleDirection::Backward);
+        assert_eq!(cycle.source(1, 1, 5), Some(2));
+    }
+
+    #[test]
+    fn ranges_past_the_palette_are_cut_off() {
+        let cycle = PaletteCycle::new(2..10, 1.0, CycleDirection::Forward);
+        assert_eq!(cycle.indices(4), 2..4);
+        assert_eq!(cycle.source(2, 1, 4), Some(3));
+        assert_eq!(cycle.source(5, 1, 4), None);
+        assert_eq!(cycle.source(0, 1, 0), None);
+    }
+}
@@ -0,0 +1,187 @@
+use bevy::prelude::*;
+
+use super::system::PixelPalette;
+
+/// A palette the day passes through at `time`, from 0 at midnight to 1 at the next midnight
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PaletteKeyframe {
+    pub time: f32,
+    pub palette: Handle<PixelPalette>,
+}
+
+impl PaletteKeyframe {
+    pub fn new(time: f32, palette: Handle<PixelPalette>) -> Self {
+        Self { time, palette }
+    }
+}
+
+/// The two keyframes around `time` and how far it is between them. Wraps around midnight. `keyframes` must be sorted
+pub fn surrounding_keyframes(
+    keyframes: &[PaletteKeyframe],
+    time: f32,
+) -> Option<(&PaletteKeyframe, &PaletteKeyframe, f32)> {
+    let first = keyframes.first()?;
+    let last = keyframes.last()?;
+    let next = keyframes.iter().position(|keyframe| keyframe.time > time);
+    let (from, to) = match next {
+        Some(0) | None => (last, first),
+        Some(next) => (&keyframes[next - 1], &keyframes[next]),
+    };
+    let span = (to.time - from.time).rem_euclid(1.0);
+    if span == 0.0 {
+        return Some((from, to, 0.0));
+    }
+    let amount = (time - from.time).rem_euclid(1.0) / span;
+    Some((from, to, amount.clamp(0.0, 1.0)))
+}
+
+/// Blends two palettes index by index. Indices only one of them has use that palette's color
+pub fn blend_palettes(from: &PixelPalette, to: &PixelPalette, amount: f32) -> Vec<Color> {
+    (0..from.colors.len().max(to.colors.len()))
+        .map(|index| {
+            let a = from.colors.get(index).or(to.colors.get(index));
+            let b = to.colors.get(index).or(from.colors.get(index));
+            match (a, b) {
+                (Some(a), Some(b)) => {
+                    let [ar, ag, ab, aa] = a.as_rgba_f32();
+                    let [br, bg, bb, ba] = b.as_rgba_f32();
+                    Color::rgba(
+                        ar + (br - ar) * amount,
+                        ag + (bg - ag) * amount,
+                        ab + (bb - ab) * amount,
+                        aa + (ba - aa) * amount,
+                    )
+                }
+                _ => Color::NONE,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layers::system::PixelLayer;
+    use crate::palette::plugin::{
+        DayNightCycle, LayerPalettes, PaletteDisplayChanged, PaletteDisplays,
+    };
+    use crate::palette::system::cycle_day_night;
+
+    use super::*;
+
+    #[test]
+    fn keyframes_wrap_around_midnight() {
+        let keyframes = [0.25, 0.75].map(|time| PaletteKeyframe::new(time, Handle::default()));
+        let times = |time| {
+            let (from, to, amount) = surrounding_keyframes(&keyframes, time).unwrap();
+            (from.time, to.time, amount)
+        };
+        assert_eq!(times(0.5), (0.25, 0.75, 0.5));
+        assert_eq!(times(0.875), (0.75, 0.25, 0.25));
+        assert_eq!(times(0.125), (0.75, 0.25, 0.75));
+        assert!(surrounding_keyframes(&[], 0.5).is_none());
+    }
+
+    #[test]
+    fn palettes_blend_index_by_index() {
+        let from = PixelPalette::new(vec![Color::BLACK, Color::RED]);
+        let to = PixelPalette::new(vec![Color::WHITE]);
+        assert_eq!(
+            blend_palettes(&from, &to, 0.5),
+            [Color::rgb(0.5, 0.5, 0.5), Color::RED]
+        );
+    }
+
+    fn app(cycle: DayNightCycle, layer_palettes: LayerPalettes) -> App {
+        let mut app = App::new();
+        app.add_plugin(TaskPoolPlugin::default())
+            .add_plugin(AssetPlugin::default())
+            .add_asset::<PixelPalette>()
+            .init_resource::<Time>()
+            .init_resource::<PaletteDisplays>()
+            .add_event::<PaletteDisplayChanged>()
+            .insert_resource(cycle)
+            .insert_resource(layer_palettes)
+            .add_system(cycle_day_night);
+        app
+    }
+
+    fn add_palette(app: &mut App, colors: Vec<Color>) -> Handle<PixelPalette> {
+        app.world
+            .resource_mut::<Assets<PixelPalette>>()
+            .add(PixelPalette::new(colors))
+    }
+
+    fn shown(app: &App) -> Option<Vec<Color>> {
+        let output = app.world.resource::<DayNightCycle>().output.clone()?;
+        app.world
+            .resource::<PaletteDisplays>()
+            .palettes
+            .get(&output.id())?
+            .colors
+            .clone()
+    }
+
+    #[test]
+    fn blends_in_whole_steps_and_snaps() {
+        let mut app = app(DayNightCycle::default(), LayerPalettes::default());
+        let night = add_palette(&mut app, vec![Color::BLACK]);
+        let day = add_palette(&mut app, vec![Color::WHITE]);
+        let snap = add_palette(&mut app, vec![Color::BLACK, Color::WHITE]);
+        app.insert_resource(
+            DayNightCycle::default()
+                .with_keyframe(0.0, night)
+                .with_keyframe(0.5, day)
+                .with_steps(4),
+        );
+        // 0.3 of the day is 60% of the way to noon which rounds down to 2 of the 4 steps
+        app.world.resource_mut::<DayNightCycle>().time = 0.3;
+        app.update();
+        assert_eq!(shown(&app), Some(vec![Color::rgb(0.5, 0.5, 0.5)]));
+        app.world.resource_mut::<DayNightCycle>().time = 0.15;
+        app.update();
+        assert_eq!(shown(&app), Some(vec![Color::rgb(0.25, 0.25, 0.25)]));
+
+        app.world.resource_mut::<DayNightCycle>().snap = Some(snap);
+        app.update();
+        assert_eq!(shown(&app), Some(vec![Color::BLACK]));
+    }
+
+    #[test]
+    fn layers_get_their_own_palette_back() {
+        let mut app = app(DayNightCycle::default(), LayerPalettes::default());
+        let own = add_palette(&mut app, vec![Color::RED]);
+        let night = add_palette(&mut app, vec![Color::BLACK]);
+        let (with_own, without) = (PixelLayer::Foreground(1), PixelLayer::Foreground(2));
+        app.insert_resource(LayerPalettes::default().with(with_own, own.clone()));
+        app.insert_resource(
+            DayNightCycle::default()
+                .with_keyframe(0.0, night)
+                .with_layer(with_own)
+                .with_layer(without),
+        );
+        app.update();
+        let output = app.world.resource::<DayNightCycle>().output.clone();
+        let layer = |app: &App, layer| {
+            app.world
+                .resource::<LayerPalettes>()
+                .layers
+                .get(&layer)
+                .cloned()
+        };
+        assert_eq!(layer(&app, with_own), output);
+        assert_eq!(layer(&app, without), output);
+
+        app.world
+            .resource_mut::<DayNightCycle>()
+            .layers
+            .retain(|layer| *layer != with_own);
+        app.update();
+        assert_eq!(layer(&app, with_own), Some(own.clone()));
+        assert_eq!(layer(&app, without), output);
+
+        app.world.remove_resource::<DayNightCycle>();
+        app.update();
+        assert_eq!(layer(&app, with_own), Some(own));
+        assert_eq!(layer(&app, without), None);
+    }
+}
@@ -0,0 +1,37 @@
+use bevy::prelude::*;
+
+use super::system::PixelPalette;
+
+/// What the palette fades toward
+#[derive(Debug, Default, Clone, PartialEq)]
+pub enum FadeTarget {
+    #[default]
+    Black,
+    White,
+    /// Each index fades toward the color at the same index in another palette
+    Palette(Handle<PixelPalette>),
+}
+
+/// How far a palette has been faded. Every index moves toward its target color by `amount`, which only ever takes
+/// whole steps so the fade looks like the old hardware ones
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FadeLevel {
+    /// The color each index fades toward. Indices past the end use the last color
+    pub targets: Vec<Color>,
+    pub amount: f32,
+    /// Snaps every in between color back to the palette so the fade never leaves it
+    pub snap: bool,
+}
+
+impl FadeLevel {
+    /// Moves `color` at `index` toward its target
+    pub fn apply(&self, index: usize, color: Color) -> Color {
+        let Some(target) = self.targets.get(index).or(self.targets.last()) else {
+            return color;
+        };
+        let [r, g, b, a] = color.as_rgba_f32();
+        let [tr, tg, tb, _] = target.as_rgba_f32();
+        let t = self.amount.clamp(0.0, 1.0);
+        Color::rgba(r + (tr - r) * t, g + (tg - g) * t, b + (tb - b) * t, a)
+    }
+}
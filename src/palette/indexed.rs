@@ -0,0 +1,255 @@
+use std::fmt;
+
+use bevy::{
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        render_resource::{AsBindGroup, Extent3d, ShaderRef, TextureDimension, TextureFormat},
+        texture::ImageSampler,
+    },
+    sprite::Material2d,
+};
+
+use super::{
+    quantize::rgba_u8,
+    system::{rgba_pixels, PaletteDisplay, PixelPalette},
+};
+
+/// The index used for transparent pixels. This means indexed images can use up to 255 palette colors
+pub const TRANSPARENT_INDEX: u8 = u8::MAX;
+
+pub const PALETTE_LOOKUP_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 255131475026685166);
+
+/// An image that stores palette indices instead of colors. Sprites using it are colored by looking the indices up in
+/// the palette when drawing, so editing the palette recolors all of them right away
+#[derive(Debug, Default, Clone, TypeUuid)]
+#[uuid = "bc899082-1009-4f92-900e-0df6baf3052f"]
+pub struct IndexedImage {
+    pub size: UVec2,
+    pub indices: Vec<u8>,
+    pub palette: Handle<PixelPalette>,
+}
+
+/// Returned by [`IndexedImage::new`] when there isn't exactly one index per pixel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexCountError {
+    pub expected: usize,
+    pub found: usize,
+}
+
+impl fmt::Display for IndexCountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "indexed image needs {} indices but has {}",
+            self.expected, self.found
+        )
+    }
+}
+
+impl std::error::Error for IndexCountError {}
+
+impl IndexedImage {
+    /// `indices` go row by row from the top left and need to have one index per pixel
+    pub fn new(
+        size: UVec2,
+        indices: Vec<u8>,
+        palette: Handle<PixelPalette>,
+    ) -> Result<Self, IndexCountError> {
+        let expected = size.x as usize * size.y as usize;
+        if indices.len() != expected {
+            return Err(IndexCountError {
+                expected,
+                found: indices.len(),
+            });
+        }
+        Ok(Self {
+            size,
+            indices,
+            palette,
+        })
+    }
+
+    /// Converts an 8 bit rgba image by matching each pixel to its closest palette color. Fully transparent pixels become
+    /// [`TRANSPARENT_INDEX`]. Returns None for other formats or palettes with more than 255 colors
+    pub fn from_image(
+        image: &Image,
+        palette_handle: Handle<PixelPalette>,
+        palette: &PixelPalette,
+    ) -> Option<Self> {
+        if palette.colors.len() > TRANSPARENT_INDEX as usize {
+            return None;
+        }
+        let indices = rgba_pixels(image)?
+            .iter()
+            .map(|&[r, g, b, a]| {
+                if a == 0 {
+                    Some(TRANSPARENT_INDEX)
+                } else {
+                    palette
+                        .nearest(Color::rgb_u8(r, g, b))
+                        .map(|index| index as u8)
+                }
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let size = image.texture_descriptor.size;
+        Self::new(UVec2::new(size.width, size.height), indices, palette_handle).ok()
+    }
+
+    /// The palette index at a pixel, row by row from the top left
+    pub fn index(&self, x: u32, y: u32) -> Option<u8> {
+        if x >= self.size.x || y >= self.size.y {
+            return None;
+        }
+        self.indices.get((y * self.size.x + x) as usize).copied()
+    }
+
+    /// The colors of the image with a palette applied, the same way the palette material draws it
+    pub fn to_rgba(
+        &self,
+        palette: &PixelPalette,
+        display: Option<&PaletteDisplay>,
+    ) -> Vec<[u8; 4]> {
+        self.indices
+            .iter()
+            .map(|index| match *index {
+                TRANSPARENT_INDEX => [0, 0, 0, 0],
+                index => rgba_u8(palette.display(index as usize, display)),
+            })
+            .collect()
+    }
+
+    /// A single channel texture holding the indices that the palette material reads from. Textures can't be empty so
+    /// an image without pixels gets a single transparent one, missing indices are transparent as well
+    pub fn index_texture(&self) -> Image {
+        let size = self.size.max(UVec2::ONE);
+        let mut indices = self.indices.clone();
+        indices.resize(size.x as usize * size.y as usize, TRANSPARENT_INDEX);
+        let mut image = Image::new(
+            Extent3d {
+                width: size.x,
+                height: size.y,
+                ..default()
+            },
+            TextureDimension::D2,
+            indices,
+            TextureFormat::R8Unorm,
+        );
+        image.sampler_descriptor = ImageSampler::nearest();
+        image
+    }
+}
+
+/// A 256x1 texture with the displayed palette colors, the last pixel is left transparent
+pub fn palette_texture(palette: &PixelPalette, display: Option<&PaletteDisplay>) -> Image {
+    let mut data = Vec::with_capacity(256 * 4);
+    for index in 0..TRANSPARENT_INDEX as usize {
+        if index < palette.colors.len() {
+            data.extend(rgba_u8(palette.display(index, display)));
+        } else {
+            data.extend([0, 0, 0, 0]);
+        }
+    }
+    data.extend([0, 0, 0, 0]);
+    let mut image = Image::new(
+        Extent3d {
+            width: 256,
+            height: 1,
+            ..default()
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    image.sampler_descriptor = ImageSampler::nearest();
+    image
+}
+
+/// Draws an index texture by looking every index up in a palette texture
+#[derive(AsBindGroup, TypeUuid, Debug, Clone)]
+#[uuid = "b74e5be2-b5f6-4adc-a440-338f395cd0bf"]
+pub struct PaletteMaterial {
+    #[texture(0)]
+    pub indices: Handle<Image>,
+    #[texture(1)]
+    pub palette: Handle<Image>,
+}
+
+impl Material2d for PaletteMaterial {
+    fn fragment_shader() -> ShaderRef {
+        PALETTE_LOOKUP_SHADER_HANDLE.typed().into()
+    }
+}
+
+/// Draws an [`IndexedImage`]. The mesh and material are added once the image is loaded
+#[derive(Component, Debug, Default, Clone)]
+pub struct IndexedSprite {
+    pub image: Handle<IndexedImage>,
+}
+
+#[derive(Bundle, Default, Clone)]
+pub struct IndexedSpriteBundle {
+    pub sprite: IndexedSprite,
+    pub transform: Transform,
+    pub global_transform: GlobalTransform,
+    pub visibility: Visibility,
+    pub computed_visibility: ComputedVisibility,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn palette() -> PixelPalette {
+        PixelPalette::new(vec![Color::rgb_u8(0, 0, 0), Color::rgb_u8(255, 0, 0)])
+    }
+
+    #[test]
+    fn new_checks_the_index_count() {
+        assert!(IndexedImage::new(UVec2::new(2, 2), vec![0; 4], Handle::default()).is_ok());
+        let error = IndexedImage::new(UVec2::new(2, 2), vec![0; 3], Handle::default()).unwrap_err();
+        assert_eq!(
+            error,
+            IndexCountError {
+                expected: 4,
+                found: 3
+            }
+        );
+        assert_eq!(error.to_string(), "indexed image needs 4 indices but has 3");
+    }
+
+    #[test]
+    fn images_are_converted_to_indices_and_back() {
+        let image = Image::new(
+            Extent3d {
+                width: 3,
+                height: 1,
+                ..default()
+            },
+            TextureDimension::D2,
+            [[250, 10, 0, 255], [5, 5, 5, 255], [255, 0, 0, 0]].concat(),
+            TextureFormat::Rgba8UnormSrgb,
+        );
+        let indexed = IndexedImage::from_image(&image, Handle::default(), &palette()).unwrap();
+        assert_eq!(indexed.indices, vec![1, 0, TRANSPARENT_INDEX]);
+        assert_eq!(indexed.index(0, 0), Some(1));
+        assert_eq!(indexed.index(3, 0), None);
+        assert_eq!(
+            indexed.to_rgba(&palette(), None),
+            vec![[255, 0, 0, 255], [0, 0, 0, 255], [0, 0, 0, 0]]
+        );
+    }
+
+    #[test]
+    fn empty_images_get_a_transparent_texture() {
+        let indexed = IndexedImage::new(UVec2::new(0, 4), Vec::new(), Handle::default()).unwrap();
+        let texture = indexed.index_texture();
+        assert_eq!(texture.texture_descriptor.size.width, 1);
+        assert_eq!(texture.texture_descriptor.size.height, 4);
+        assert_eq!(texture.data, vec![TRANSPARENT_INDEX; 4]);
+
+        let indexed = IndexedImage::default();
+        assert_eq!(indexed.index_texture().data, vec![TRANSPARENT_INDEX]);
+    }
+}
@@ -0,0 +1,380 @@
+use std::fmt;
+
+use bevy::{
+    asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset},
+    prelude::*,
+    render::texture::{CompressedImageFormats, ImageType},
+};
+
+use super::system::{rgba_pixels, PixelPalette};
+
+/// Everything that can go wrong while reading a palette file. Line numbers start at 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaletteLoadError {
+    NotText,
+    MissingHeader { expected: &'static str },
+    InvalidLine { line: usize, content: String },
+    WrongColorCount { expected: usize, found: usize },
+    InvalidImage(String),
+    NotAStrip { width: u32, height: u32 },
+    UnknownFormat(String),
+    Empty,
+}
+
+impl fmt::Display for PaletteLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaletteLoadError::NotText => write!(f, "palette file is not valid utf-8 text"),
+            PaletteLoadError::MissingHeader { expected } => {
+                write!(f, "palette file is missing the `{expected}` header")
+            }
+            PaletteLoadError::InvalidLine { line, content } => {
+                write!(f, "line {line} is not a valid color: `{content}`")
+            }
+            PaletteLoadError::WrongColorCount { expected, found } => {
+                write!(f, "palette says it has {expected} colors but has {found}")
+            }
+            PaletteLoadError::InvalidImage(error) => {
+                write!(f, "palette image could not be read: {error}")
+            }
+            PaletteLoadError::NotAStrip { width, height } => write!(
+                f,
+                "palette image is {width}x{height} but has to be a single row or column of pixels"
+            ),
+            PaletteLoadError::UnknownFormat(path) => {
+                write!(f, "`{path}` is not a known palette format")
+            }
+            PaletteLoadError::Empty => write!(f, "palette has no colors"),
+        }
+    }
+}
+
+impl std::error::Error for PaletteLoadError {}
+
+/// Lines that aren't blank, trimmed and numbered from 1
+fn content_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+}
+
+/// Reads `r g b` from the start of a line, anything after is ignored(color names in gpl files)
+fn parse_rgb(line: usize, content: &str) -> Result<Color, PaletteLoadError> {
+    let invalid = || PaletteLoadError::InvalidLine {
+        line,
+        content: content.to_string(),
+    };
+    let mut channels = content
+        .split_whitespace()
+        .map(|channel| channel.parse::<u8>());
+    match (channels.next(), channels.next(), channels.next()) {
+        (Some(Ok(r)), Some(Ok(g)), Some(Ok(b))) => Ok(Color::rgb_u8(r, g, b)),
+        _ => Err(invalid()),
+    }
+}
+
+fn non_empty(colors: Vec<Color>) -> Result<PixelPalette, PaletteLoadError> {
+    if colors.is_empty() {
+        Err(PaletteLoadError::Empty)
+    } else {
+        Ok(PixelPalette::new(colors))
+    }
+}
+
+/// GIMP palettes. Starts with `GIMP Palette`, then optional `Name:`/`Columns:` lines, `#` comments and `r g b name` lines
+pub fn parse_gpl(text: &str) -> Result<PixelPalette, PaletteLoadError> {
+    let mut lines = content_lines(text);
+    match lines.next() {
+        Some((_, "GIMP Palette")) => (),
+        _ => {
+            return Err(PaletteLoadError::MissingHeader {
+                expected: "GIMP Palette",
+            })
+        }
+    }
+    let colors = lines
+        .filter(|(_, line)| {
+            !line.starts_with('#') && !line.starts_with("Name:") && !line.starts_with("Columns:")
+        })
+        .map(|(line, content)| parse_rgb(line, content))
+        .collect::<Result<Vec<_>, _>>()?;
+    non_empty(colors)
+}
+
+/// JASC palettes from Paint Shop Pro. `JASC-PAL`, a version, the color count and then one `r g b` per line
+pub fn parse_jasc_pal(text: &str) -> Result<PixelPalette, PaletteLoadError> {
+    let mut lines = content_lines(text);
+    match lines.next() {
+        Some((_, "JASC-PAL")) => (),
+        _ => {
+            return Err(PaletteLoadError::MissingHeader {
+                expected: "JASC-PAL",
+            })
+        }
+    }
+    match lines.next() {
+        Some((_, "0100")) => (),
+        _ => return Err(PaletteLoadError::MissingHeader { expected: "0100" }),
+    }
+    let expected = match lines.next() {
+        Some((line, content)) => {
+            content
+                .parse::<usize>()
+                .map_err(|_| PaletteLoadError::InvalidLine {
+                    line,
+                    content: content.to_string(),
+                })?
+        }
+        None => return Err(PaletteLoadError::Empty),
+    };
+    let colors = lines
+        .map(|(line, content)| parse_rgb(line, content))
+        .collect::<Result<Vec<_>, _>>()?;
+    if colors.len() != expected {
+        return Err(PaletteLoadError::WrongColorCount {
+            expected,
+            found: colors.len(),
+        });
+    }
+    non_empty(colors)
+}
+
+/// Lospec style hex palettes, one `rrggbb` per line with an optional `#`
+pub fn parse_hex(text: &str) -> Result<PixelPalette, PaletteLoadError> {
+    let colors = content_lines(text)
+        .map(|(line, content)| {
+            let hex = content.trim_start_matches('#');
+            match (hex.len(), u32::from_str_radix(hex, 16)) {
+                (6, Ok(color)) => {
+                    let [_, r, g, b] = color.to_be_bytes();
+                    Ok(Color::rgb_u8(r, g, b))
+                }
+                _ => Err(PaletteLoadError::InvalidLine {
+                    line,
+                    content: content.to_string(),
+                }),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    non_empty(colors)
+}
+
+/// A png that is a single row or column of pixels, each pixel being one palette color
+pub fn parse_swatch(bytes: &[u8]) -> Result<PixelPalette, PaletteLoadError> {
+    let image = Image::from_buffer(
+        bytes,
+        ImageType::Extension("png"),
+        CompressedImageFormats::NONE,
+        true,
+    )
+    .map_err(|error| PaletteLoadError::InvalidImage(error.to_string()))?;
+    let size = image.texture_descriptor.size;
+    if size.width != 1 && size.height != 1 {
+        return Err(PaletteLoadError::NotAStrip {
+            width: size.width,
+            height: size.height,
+        });
+    }
+    let pixels = rgba_pixels(&image).ok_or_else(|| {
+        PaletteLoadError::InvalidImage(format!(
+            "{:?} is not an 8 bit format",
+            image.texture_descriptor.format
+        ))
+    })?;
+    non_empty(
+        pixels
+            .iter()
+            .map(|[r, g, b, a]| Color::rgba_u8(*r, *g, *b, *a))
+            .collect(),
+    )
+}
+
+/// Loads `.gpl`, `.pal`, `.hex` and `.palette.png` files as a [`PixelPalette`]. Palettes are reloaded like any other
+/// asset when the asset server is watching for changes
+#[derive(Default)]
+pub struct PixelPaletteLoader;
+
+impl AssetLoader for PixelPaletteLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let path = load_context.path().to_string_lossy().to_lowercase();
+            let text = || std::str::from_utf8(bytes).map_err(|_| PaletteLoadError::NotText);
+            let palette = if path.ends_with(".palette.png") {
+                parse_swatch(bytes)?
+            } else if path.ends_with(".gpl") {
+                parse_gpl(text()?)?
+            } else if path.ends_with(".pal") {
+                parse_jasc_pal(text()?)?
+            } else if path.ends_with(".hex") {
+                parse_hex(text()?)?
+            } else {
+                return Err(PaletteLoadError::UnknownFormat(path).into());
+            };
+            load_context.set_default_asset(LoadedAsset::new(palette));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["gpl", "pal", "hex", "palette.png"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 3x1 png of red, green and half transparent blue
+    const SWATCH_PNG: &[u8] = &[
+        137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 3, 0, 0, 0, 1, 8, 6,
+        0, 0, 0, 27, 224, 20, 180, 0, 0, 0, 18, 73, 68, 65, 84, 120, 218, 99, 248, 207, 192, 240,
+        31, 12, 25, 254, 55, 0, 0, 35, 106, 5, 124, 248, 191, 29, 144, 0, 0, 0, 0, 73, 69, 78, 68,
+        174, 66, 96, 130,
+    ];
+
+    // 2x2 black png
+    const SQUARE_PNG: &[u8] = &[
+        137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 2, 0, 0, 0, 2, 8, 6,
+        0, 0, 0, 114, 182, 13, 36, 0, 0, 0, 16, 73, 68, 65, 84, 120, 218, 99, 96, 96, 96, 248, 15,
+        197, 16, 6, 0, 29, 244, 3, 253, 32, 224, 89, 39, 0, 0, 0, 0, 73, 69, 78, 68, 174, 66, 96,
+        130,
+    ];
+
+    fn rgb() -> Vec<Color> {
+        vec![
+            Color::rgb_u8(255, 0, 0),
+            Color::rgb_u8(0, 255, 0),
+            Color::rgb_u8(0, 0, 255),
+        ]
+    }
+
+    #[test]
+    fn gpl_palettes_are_parsed() {
+        let text = "GIMP Palette\nName: Test\nColumns: 3\n# comment\n255 0 0 Red\n  0 255   0\tGreen\n0 0 255\n";
+        assert_eq!(parse_gpl(text).unwrap().colors, rgb());
+    }
+
+    #[test]
+    fn gpl_errors() {
+        assert_eq!(
+            parse_gpl("255 0 0\n").unwrap_err(),
+            PaletteLoadError::MissingHeader {
+                expected: "GIMP Palette"
+            }
+        );
+        assert_eq!(
+            parse_gpl("GIMP Palette\n255 0 0\n\n255 0\n").unwrap_err(),
+            PaletteLoadError::InvalidLine {
+                line: 4,
+                content: "255 0".to_string()
+            }
+        );
+        assert_eq!(
+            parse_gpl("GIMP Palette\n256 0 0\n")
+                .unwrap_err()
+                .to_string(),
+            "line 2 is not a valid color: `256 0 0`"
+        );
+        assert_eq!(
+            parse_gpl("GIMP Palette\nName: Empty\n").unwrap_err(),
+            PaletteLoadError::Empty
+        );
+    }
+
+    #[test]
+    fn jasc_palettes_are_parsed() {
+        let text = "JASC-PAL\r\n0100\r\n3\r\n255 0 0\r\n0 255 0\r\n0 0 255\r\n";
+        assert_eq!(parse_jasc_pal(text).unwrap().colors, rgb());
+    }
+
+    #[test]
+    fn jasc_errors() {
+        assert_eq!(
+            parse_jasc_pal("GIMP Palette\n").unwrap_err(),
+            PaletteLoadError::MissingHeader {
+                expected: "JASC-PAL"
+            }
+        );
+        assert_eq!(
+            parse_jasc_pal("JASC-PAL\n0200\n").unwrap_err(),
+            PaletteLoadError::MissingHeader { expected: "0100" }
+        );
+        assert_eq!(
+            parse_jasc_pal("JASC-PAL\n0100\nthree\n").unwrap_err(),
+            PaletteLoadError::InvalidLine {
+                line: 3,
+                content: "three".to_string()
+            }
+        );
+        let error = parse_jasc_pal("JASC-PAL\n0100\n2\n255 0 0\n").unwrap_err();
+        assert_eq!(
+            error,
+            PaletteLoadError::WrongColorCount {
+                expected: 2,
+                found: 1
+            }
+        );
+        assert_eq!(error.to_string(), "palette says it has 2 colors but has 1");
+        assert_eq!(
+            parse_jasc_pal("JASC-PAL\n0100\n0\n").unwrap_err(),
+            PaletteLoadError::Empty
+        );
+    }
+
+    #[test]
+    fn hex_palettes_are_parsed() {
+        assert_eq!(
+            parse_hex("ff0000\n#00FF00\n\n0000ff").unwrap().colors,
+            rgb()
+        );
+    }
+
+    #[test]
+    fn hex_errors() {
+        assert_eq!(
+            parse_hex("ff0000\nfff\n").unwrap_err(),
+            PaletteLoadError::InvalidLine {
+                line: 2,
+                content: "fff".to_string()
+            }
+        );
+        assert_eq!(
+            parse_hex("ff0000\n#gg0000\n").unwrap_err().to_string(),
+            "line 2 is not a valid color: `#gg0000`"
+        );
+        assert_eq!(parse_hex("\n\n").unwrap_err(), PaletteLoadError::Empty);
+    }
+
+    #[test]
+    fn swatches_are_parsed() {
+        let colors = parse_swatch(SWATCH_PNG).unwrap().colors;
+        assert_eq!(
+            colors,
+            vec![
+                Color::rgba_u8(255, 0, 0, 255),
+                Color::rgba_u8(0, 255, 0, 255),
+                Color::rgba_u8(0, 0, 255, 128),
+            ]
+        );
+    }
+
+    #[test]
+    fn swatch_errors() {
+        assert_eq!(
+            parse_swatch(SQUARE_PNG).unwrap_err(),
+            PaletteLoadError::NotAStrip {
+                width: 2,
+                height: 2
+            }
+        );
+        assert!(matches!(
+            parse_swatch(b"not a png").unwrap_err(),
+            PaletteLoadError::InvalidImage(_)
+        ));
+    }
+}
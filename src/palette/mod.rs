@@ -0,0 +1,9 @@
+pub mod cycle;
+pub mod daynight;
+pub mod fade;
+pub mod indexed;
+pub mod loader;
+pub mod plugin;
+pub mod quantize;
+pub mod swap;
+pub mod system;
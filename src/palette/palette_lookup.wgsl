@@ -0,0 +1,20 @@
+@group(1) @binding(0)
+var indices: texture_2d<f32>;
+@group(1) @binding(1)
+var palette: texture_2d<f32>;
+
+struct FragmentInput {
+    #import bevy_sprite::mesh2d_vertex_output
+};
+
+// Index 255 is transparent, anything else is looked up in the palette texture
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(indices));
+    let position = vec2<i32>(min(in.uv * size, size - 1.0));
+    let index = i32(round(textureLoad(indices, position, 0).r * 255.0));
+    if (index == 255) {
+        discard;
+    }
+    return textureLoad(palette, vec2<i32>(index, 0), 0);
+}
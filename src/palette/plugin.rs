@@ -0,0 +1,321 @@
+use bevy::{
+    asset::{load_internal_asset, HandleId},
+    prelude::*,
+    sprite::Material2dPlugin,
+    utils::HashMap,
+};
+
+use crate::layers::system::PixelLayer;
+
+use super::{
+    daynight::PaletteKeyframe,
+    fade::FadeTarget,
+    indexed::{IndexedImage, PaletteMaterial, PALETTE_LOOKUP_SHADER_HANDLE},
+    loader::PixelPaletteLoader,
+    quantize::Dither,
+    system::{
+        apply_palette_swaps, cycle_day_night, cycle_palettes, fade_palettes,
+        prepare_indexed_images, quantize_images, refresh_palette_swaps, snap_clear_colors,
+        snap_material_colors, snap_sprite_colors, spawn_indexed_sprites, update_palette_textures,
+        PaletteDisplay, PixelPalette,
+    },
+};
+
+/// The palette every sprite color, ColorMaterial color and clear color is mapped to. Swap the handle or edit the
+/// palette at runtime and everything gets mapped again from its original color
+#[derive(Resource, Default, Clone)]
+pub struct ActivePalette {
+    pub palette: Handle<PixelPalette>,
+}
+
+impl ActivePalette {
+    pub fn new(palette: Handle<PixelPalette>) -> Self {
+        Self { palette }
+    }
+}
+
+/// Gives PixelLayers their own palette like the separate background and sprite banks on the NES. Sprites map to the
+/// palette of their layer, then the one for all background or foreground layers, then [`ActivePalette`]
+#[derive(Resource, Debug, Default, Clone)]
+pub struct LayerPalettes {
+    pub layers: HashMap<PixelLayer, Handle<PixelPalette>>,
+    pub background: Option<Handle<PixelPalette>>,
+    pub foreground: Option<Handle<PixelPalette>>,
+}
+
+impl LayerPalettes {
+    pub fn with(mut self, layer: PixelLayer, palette: Handle<PixelPalette>) -> Self {
+        self.layers.insert(layer, palette);
+        self
+    }
+
+    pub fn with_background(mut self, palette: Handle<PixelPalette>) -> Self {
+        self.background = Some(palette);
+        self
+    }
+
+    pub fn with_foreground(mut self, palette: Handle<PixelPalette>) -> Self {
+        self.foreground = Some(palette);
+        self
+    }
+
+    /// Every palette used by a layer
+    pub fn iter(&self) -> impl Iterator<Item = &Handle<PixelPalette>> {
+        self.layers
+            .values()
+            .chain(self.background.iter())
+            .chain(self.foreground.iter())
+    }
+
+    /// The palette for a layer if it has one
+    pub fn get(&self, layer: PixelLayer) -> Option<&Handle<PixelPalette>> {
+        self.layers.get(&layer).or(match layer {
+            PixelLayer::Background(_) => self.background.as_ref(),
+            PixelLayer::Foreground(_) => self.foreground.as_ref(),
+        })
+    }
+}
+
+/// Moves through keyframed palettes over the day. The blend between two keyframes only takes `steps` whole steps and
+/// can be snapped to a master palette, so it still looks limited instead of smoothly tinted. The keyframes should
+/// line up index by index. The result is shown through its own palette which is used for the given `layers` through
+/// [`LayerPalettes`], leave the UI layers out and they stay as they are
+#[derive(Resource, Debug, Clone)]
+pub struct DayNightCycle {
+    pub keyframes: Vec<PaletteKeyframe>,
+    /// Seconds in a day. 0 stops time so it can be set by hand
+    pub day_length: f32,
+    /// The time of day from 0 to 1
+    pub time: f32,
+    pub steps: u32,
+    pub snap: Option<Handle<PixelPalette>>,
+    pub layers: Vec<PixelLayer>,
+    /// The palette the blend is shown on, made the first time the cycle runs. It has the colors of the first keyframe
+    pub output: Option<Handle<PixelPalette>>,
+}
+
+impl Default for DayNightCycle {
+    fn default() -> Self {
+        Self {
+            keyframes: Vec::new(),
+            day_length: 0.0,
+            time: 0.0,
+            steps: 4,
+            snap: None,
+            layers: Vec::new(),
+            output: None,
+        }
+    }
+}
+
+impl DayNightCycle {
+    pub fn new(day_length: f32) -> Self {
+        Self {
+            day_length,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_keyframe(mut self, time: f32, palette: Handle<PixelPalette>) -> Self {
+        self.keyframes.push(PaletteKeyframe::new(time, palette));
+        self.keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        self
+    }
+
+    pub fn with_layer(mut self, layer: PixelLayer) -> Self {
+        self.layers.push(layer);
+        self
+    }
+
+    pub fn with_steps(mut self, steps: u32) -> Self {
+        self.steps = steps;
+        self
+    }
+
+    pub fn with_snap(mut self, palette: Handle<PixelPalette>) -> Self {
+        self.snap = Some(palette);
+        self
+    }
+}
+
+/// How an image is quantized
+#[derive(Debug, Default, Clone)]
+pub struct QuantizeSettings {
+    pub palette: Handle<PixelPalette>,
+    pub dither: Dither,
+}
+
+/// Images that get every pixel remapped to a palette as soon as they are loaded. Useful for art that is slightly off from the palette
+#[derive(Resource, Debug, Default, Clone)]
+pub struct QuantizedImages {
+    pub images: HashMap<Handle<Image>, QuantizeSettings>,
+}
+
+impl QuantizedImages {
+    pub fn add(&mut self, image: Handle<Image>, palette: Handle<PixelPalette>) {
+        self.images.insert(
+            image,
+            QuantizeSettings {
+                palette,
+                dither: Dither::None,
+            },
+        );
+    }
+
+    pub fn add_dithered(
+        &mut self,
+        image: Handle<Image>,
+        palette: Handle<PixelPalette>,
+        dither: Dither,
+    ) {
+        self.images
+            .insert(image, QuantizeSettings { palette, dither });
+    }
+}
+
+/// Fades the active palette and the ones in [`LayerPalettes`] toward black, white or another palette a whole step at
+/// a time. Insert it to start a fade and a [`PaletteFadeFinished`] event is sent once it's done. Fading in starts at
+/// the target and steps back to the palette. Removing it puts the palettes back
+#[derive(Resource, Debug, Clone)]
+pub struct PaletteFade {
+    pub target: FadeTarget,
+    pub steps: u32,
+    /// Seconds between steps
+    pub step_time: f32,
+    pub fade_in: bool,
+    /// Keeps every in between color inside the palette
+    pub snap: bool,
+    pub step: u32,
+    pub elapsed: f32,
+    reported: bool,
+}
+
+impl PaletteFade {
+    pub fn fade_out(target: FadeTarget, steps: u32, step_time: f32) -> Self {
+        Self {
+            target,
+            steps,
+            step_time,
+            fade_in: false,
+            snap: false,
+            step: 0,
+            elapsed: 0.0,
+            reported: false,
+        }
+    }
+
+    pub fn fade_in(target: FadeTarget, steps: u32, step_time: f32) -> Self {
+        Self {
+            fade_in: true,
+            ..Self::fade_out(target, steps, step_time)
+        }
+    }
+
+    pub fn with_snap(mut self) -> Self {
+        self.snap = true;
+        self
+    }
+
+    /// How far toward the target the palette is right now
+    pub fn amount(&self) -> f32 {
+        let amount = self.step.min(self.steps) as f32 / self.steps.max(1) as f32;
+        match self.fade_in {
+            true => 1.0 - amount,
+            false => amount,
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.step >= self.steps
+    }
+
+    /// Returns true the first time it's called after the fade finished
+    pub(crate) fn report(&mut self) -> bool {
+        let report = self.finished() && !self.reported;
+        self.reported |= report;
+        report
+    }
+}
+
+/// Sent when a [`PaletteFade`] reaches its last step
+#[derive(Debug, Clone)]
+pub struct PaletteFadeFinished {
+    pub target: FadeTarget,
+    pub fade_in: bool,
+}
+
+/// The gpu side of an [`IndexedImage`]
+#[derive(Debug, Clone)]
+pub struct IndexedTexture {
+    pub indices: Handle<Image>,
+    pub material: Handle<PaletteMaterial>,
+    pub mesh: Handle<Mesh>,
+}
+
+/// The textures and materials made for every indexed image and the palettes they use
+#[derive(Resource, Debug, Default, Clone)]
+pub struct IndexedTextures {
+    pub images: HashMap<HandleId, IndexedTexture>,
+    pub palettes: HashMap<HandleId, Handle<Image>>,
+}
+
+/// How every palette is shown right now, see [`PaletteDisplay`]. Palettes that are shown as they are have no entry
+#[derive(Resource, Debug, Default, Clone)]
+pub struct PaletteDisplays {
+    pub palettes: HashMap<HandleId, PaletteDisplay>,
+}
+
+/// Sent when a palette is shown differently without the palette itself changing
+#[derive(Debug, Clone)]
+pub struct PaletteDisplayChanged {
+    pub palette: HandleId,
+}
+
+/// The swapped images made for [`super::swap::PaletteSwap`], keyed by the original image and the from and to palettes.
+/// The handles are weak so a swapped image goes away with the last sprite using it, its entry is dropped then too
+#[derive(Resource, Debug, Default, Clone)]
+pub struct PaletteSwapCache {
+    pub images: HashMap<(HandleId, HandleId, HandleId), Handle<Image>>,
+}
+
+/// The plugin for limited palettes. Nothing is mapped until [`ActivePalette`] points to a loaded palette.
+/// Palettes can be loaded from `.gpl`, `.pal`, `.hex` and `.palette.png` files
+pub struct PixelPalettePlugin;
+
+impl Plugin for PixelPalettePlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            PALETTE_LOOKUP_SHADER_HANDLE,
+            "palette_lookup.wgsl",
+            Shader::from_wgsl
+        );
+        app.add_asset::<PixelPalette>()
+            .add_asset::<IndexedImage>()
+            .add_plugin(Material2dPlugin::<PaletteMaterial>::default())
+            .init_asset_loader::<PixelPaletteLoader>()
+            .init_resource::<ActivePalette>()
+            .init_resource::<ClearColor>()
+            .init_resource::<QuantizedImages>()
+            .init_resource::<IndexedTextures>()
+            .init_resource::<PaletteSwapCache>()
+            .init_resource::<LayerPalettes>()
+            .init_resource::<PaletteDisplays>()
+            .add_event::<PaletteFadeFinished>()
+            .add_event::<PaletteDisplayChanged>()
+            .add_systems((
+                cycle_palettes,
+                cycle_day_night,
+                fade_palettes,
+                snap_sprite_colors,
+                snap_material_colors,
+                snap_clear_colors,
+                quantize_images,
+                prepare_indexed_images,
+                update_palette_textures.after(prepare_indexed_images),
+                spawn_indexed_sprites.after(prepare_indexed_images),
+                apply_palette_swaps,
+                refresh_palette_swaps.before(apply_palette_swaps),
+            ));
+    }
+}
@@ -0,0 +1,276 @@
+use bevy::{prelude::*, utils::HashMap};
+
+use super::system::{rgba_pixels, set_rgba_pixels, PixelPalette};
+
+/// How the error from snapping to a palette is spread out. Every option gives the same result for the same input.
+/// Ordered dithering isn't idempotent, running it over its own output can move pixels again. Always dither from the
+/// original pixels, [`super::system::quantize_images`] keeps them around for that
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Dither {
+    #[default]
+    None,
+    Bayer2,
+    Bayer4,
+    Bayer8,
+    FloydSteinberg,
+    Atkinson,
+}
+
+/// The threshold of an ordered dither pattern of `size` at a position, between 0 and 1
+pub fn bayer_threshold(size: u32, x: u32, y: u32) -> f32 {
+    let mut value = 0;
+    let mut bit = 1;
+    while bit < size {
+        let (bx, by) = (x & bit != 0, y & bit != 0);
+        value = value * 4 + ((bx ^ by) as u32 * 2 + by as u32);
+        bit *= 2;
+    }
+    (value as f32 + 0.5) / (size * size) as f32
+}
+
+/// Converts a color to 8 bit srgb
+pub fn rgba_u8(color: Color) -> [u8; 4] {
+    color
+        .as_rgba_f32()
+        .map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+/// Finds the closest palette color of 8 bit colors, remembering the ones it has seen
+struct NearestCache<'a> {
+    palette: &'a PixelPalette,
+    cache: HashMap<[u8; 3], [u8; 3]>,
+}
+
+impl<'a> NearestCache<'a> {
+    fn new(palette: &'a PixelPalette) -> Self {
+        Self {
+            palette,
+            cache: HashMap::default(),
+        }
+    }
+
+    fn get(&mut self, rgb: [f32; 3]) -> [u8; 3] {
+        let [r, g, b, _] = rgba_u8(Color::rgb(rgb[0], rgb[1], rgb[2]));
+        let palette = self.palette;
+        *self.cache.entry([r, g, b]).or_insert_with(|| {
+            let [r, g, b, _] = rgba_u8(palette.snap(Color::rgb_u8(r, g, b)));
+            [r, g, b]
+        })
+    }
+}
+
+/// Remaps a buffer of 8 bit srgb pixels that is `width` pixels wide to a palette. Fully transparent pixels are skipped
+/// and alpha is kept as is. Works for gradients made on the cpu as well as images. See [`Dither`] for why this should
+/// be given the original pixels rather than ones that were already dithered
+pub fn quantize_pixels(
+    pixels: &mut [[u8; 4]],
+    width: usize,
+    palette: &PixelPalette,
+    dither: Dither,
+) {
+    if palette.colors.is_empty() || width == 0 {
+        return;
+    }
+    let mut nearest = NearestCache::new(palette);
+    // How far ordered dithering pushes colors around, roughly the distance between palette colors
+    let spread = 1.0 / (palette.colors.len() as f32).cbrt();
+    let mut errors = vec![[0.0f32; 3]; pixels.len()];
+    let height = pixels.len() / width;
+    for y in 0..height {
+        for x in 0..width {
+            let index = y * width + x;
+            let [r, g, b, a] = pixels[index];
+            if a == 0 {
+                continue;
+            }
+            let mut rgb = [r, g, b].map(|channel| channel as f32 / 255.0);
+            match dither {
+                Dither::Bayer2 | Dither::Bayer4 | Dither::Bayer8 => {
+                    let size = match dither {
+                        Dither::Bayer2 => 2,
+                        Dither::Bayer4 => 4,
+                        _ => 8,
+                    };
+                    let offset = (bayer_threshold(size, x as u32, y as u32) - 0.5) * spread;
+                    rgb = rgb.map(|channel| channel + offset);
+                }
+                Dither::FloydSteinberg | Dither::Atkinson => {
+                    for (channel, error) in rgb.iter_mut().zip(errors[index]) {
+                        *channel += error;
+                    }
+                }
+                Dither::None => (),
+            }
+            let [nr, ng, nb] = nearest.get(rgb);
+            pixels[index] = [nr, ng, nb, a];
+
+            let targets: &[(isize, usize, f32)] = match dither {
+                Dither::FloydSteinberg => &[
+                    (1, 0, 7.0 / 16.0),
+                    (-1, 1, 3.0 / 16.0),
+                    (0, 1, 5.0 / 16.0),
+                    (1, 1, 1.0 / 16.0),
+                ],
+                Dither::Atkinson => &[
+                    (1, 0, 1.0 / 8.0),
+                    (2, 0, 1.0 / 8.0),
+                    (-1, 1, 1.0 / 8.0),
+                    (0, 1, 1.0 / 8.0),
+                    (1, 1, 1.0 / 8.0),
+                    (0, 2, 1.0 / 8.0),
+                ],
+                _ => &[],
+            };
+            let error = [
+                rgb[0] - nr as f32 / 255.0,
+                rgb[1] - ng as f32 / 255.0,
+                rgb[2] - nb as f32 / 255.0,
+            ];
+            for (dx, dy, weight) in targets {
+                let tx = x as isize + dx;
+                let ty = y + dy;
+                if tx < 0 || tx as usize >= width || ty >= height {
+                    continue;
+                }
+                let target = &mut errors[ty * width + tx as usize];
+                for channel in 0..3 {
+                    target[channel] += error[channel] * weight;
+                }
+            }
+        }
+    }
+}
+
+/// Remaps every pixel that isn't fully transparent to its closest palette color, alpha is kept as is.
+/// Returns false without touching the image if its format isn't 8 bit rgba
+pub fn quantize_image(image: &mut Image, palette: &PixelPalette, dither: Dither) -> bool {
+    let mut pixels = match rgba_pixels(image) {
+        Some(pixels) => pixels,
+        None => return false,
+    };
+    let width = image.texture_descriptor.size.width as usize;
+    quantize_pixels(&mut pixels, width, palette, dither);
+    set_rgba_pixels(image, &pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+    use super::*;
+
+    fn palette(colors: &[[u8; 3]]) -> PixelPalette {
+        PixelPalette::new(
+            colors
+                .iter()
+                .map(|[r, g, b]| Color::rgb_u8(*r, *g, *b))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn pixels_snap_to_the_nearest_color() {
+        let palette = palette(&[[0, 0, 0], [255, 255, 255], [255, 0, 0]]);
+        let mut pixels = [[20, 10, 30, 255], [240, 250, 235, 255], [200, 40, 30, 255]];
+        quantize_pixels(&mut pixels, 3, &palette, Dither::None);
+        assert_eq!(
+            pixels,
+            [[0, 0, 0, 255], [255, 255, 255, 255], [255, 0, 0, 255]]
+        );
+    }
+
+    #[test]
+    fn transparent_pixels_are_skipped_and_alpha_is_kept() {
+        let palette = palette(&[[0, 0, 0], [255, 255, 255]]);
+        let mut pixels = [[200, 100, 50, 0], [250, 250, 250, 128]];
+        quantize_pixels(&mut pixels, 2, &palette, Dither::None);
+        assert_eq!(pixels, [[200, 100, 50, 0], [255, 255, 255, 128]]);
+    }
+
+    #[test]
+    fn images_that_arent_rgba_are_left_alone() {
+        let palette = palette(&[[0, 0, 0]]);
+        let size = Extent3d {
+            width: 1,
+            height: 1,
+            depth_or_array_layers: 1,
+        };
+        let mut image = Image::new(
+            size,
+            TextureDimension::D2,
+            vec![255, 0, 0, 255],
+            TextureFormat::R32Float,
+        );
+        assert!(!quantize_image(&mut image, &palette, Dither::None));
+        assert_eq!(image.data, vec![255, 0, 0, 255]);
+
+        let mut image = Image::new(
+            size,
+            TextureDimension::D2,
+            vec![10, 10, 10, 255],
+            TextureFormat::Rgba8UnormSrgb,
+        );
+        assert!(quantize_image(&mut image, &palette, Dither::None));
+        assert_eq!(image.data, vec![0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn bayer_matrices_use_every_threshold_once() {
+        let matrix = [(0, 0), (1, 0), (0, 1), (1, 1)]
+            .map(|(x, y)| (bayer_threshold(2, x, y) * 4.0 - 0.5) as u32);
+        assert_eq!(matrix, [0, 2, 3, 1]);
+        for size in [4, 8] {
+            let mut values = (0..size * size)
+                .map(|index| {
+                    (bayer_threshold(size, index % size, index / size) * (size * size) as f32)
+                        as u32
+                })
+                .collect::<Vec<_>>();
+            values.sort();
+            assert_eq!(values, (0..size * size).collect::<Vec<_>>());
+        }
+        // The pattern repeats every size pixels
+        assert_eq!(bayer_threshold(4, 1, 2), bayer_threshold(4, 5, 6));
+    }
+
+    fn dithered_gray(dither: Dither) -> Vec<[u8; 4]> {
+        let palette = palette(&[[0, 0, 0], [255, 255, 255]]);
+        let mut pixels = vec![[128, 128, 128, 255]; 64];
+        quantize_pixels(&mut pixels, 8, &palette, dither);
+        pixels
+    }
+
+    fn white_count(pixels: &[[u8; 4]]) -> usize {
+        pixels.iter().filter(|pixel| pixel[0] == 255).count()
+    }
+
+    #[test]
+    fn ordered_dithering_mixes_colors() {
+        assert_eq!(white_count(&dithered_gray(Dither::None)), 64);
+        let pixels = dithered_gray(Dither::Bayer4);
+        let white = white_count(&pixels);
+        assert!(white > 16 && white < 64, "{white}");
+        // Each 4x4 tile gets the same pattern
+        assert_eq!(pixels[0], pixels[4]);
+        assert_eq!(pixels[9], pixels[8 * 4 + 9]);
+    }
+
+    #[test]
+    fn error_diffusion_mixes_colors_deterministically() {
+        for dither in [Dither::FloydSteinberg, Dither::Atkinson] {
+            let pixels = dithered_gray(dither);
+            let white = white_count(&pixels);
+            assert!(white > 16 && white < 64, "{dither:?} {white}");
+            assert_eq!(pixels, dithered_gray(dither), "{dither:?}");
+        }
+    }
+
+    #[test]
+    fn error_diffusion_leaves_palette_colors_alone() {
+        let palette = palette(&[[0, 0, 0], [255, 255, 255]]);
+        let mut pixels = dithered_gray(Dither::FloydSteinberg);
+        let dithered = pixels.clone();
+        quantize_pixels(&mut pixels, 8, &palette, Dither::FloydSteinberg);
+        assert_eq!(pixels, dithered);
+    }
+}
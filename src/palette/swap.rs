@@ -0,0 +1,105 @@
+use bevy::prelude::*;
+
+use super::{
+    quantize::rgba_u8,
+    system::{rgba_pixels, set_rgba_pixels, PixelPalette},
+};
+
+/// Recolors a sprite by replacing every color of `from` with the color at the same index in `to`. Useful for team
+/// colors, damage flashes and enemy variants without shipping a sprite sheet for each.
+/// Only sprites drawn from a `Handle<Image>` are swapped. [`TextureAtlasSprite`]s aren't supported since every sprite
+/// of an atlas shares its texture, give those their own swapped atlas instead
+#[derive(Component, Debug, Default, Clone, PartialEq, Eq)]
+pub struct PaletteSwap {
+    pub from: Handle<PixelPalette>,
+    pub to: Handle<PixelPalette>,
+}
+
+impl PaletteSwap {
+    pub fn new(from: Handle<PixelPalette>, to: Handle<PixelPalette>) -> Self {
+        Self { from, to }
+    }
+}
+
+/// The image a sprite had before it was swapped, so it can be put back once the swap is removed, and the swapped copy
+/// it was given. A different image means the sprite was given a new one which gets swapped instead
+#[derive(Component, Debug, Clone)]
+pub struct PaletteSwapSource {
+    pub original: Handle<Image>,
+    pub swapped: Handle<Image>,
+}
+
+/// Makes a copy of the image with the colors swapped index by index. Colors that aren't in `from` use their closest
+/// match and indices past the end of `to` are left alone. Cycles and fades of `to` aren't baked in. Returns None if
+/// the image isn't 8 bit rgba
+pub fn swap_image(image: &Image, from: &PixelPalette, to: &PixelPalette) -> Option<Image> {
+    let from_colors = from
+        .colors
+        .iter()
+        .map(|color| {
+            let [r, g, b, _] = rgba_u8(*color);
+            [r, g, b]
+        })
+        .collect::<Vec<_>>();
+    let pixels = rgba_pixels(image)?
+        .into_iter()
+        .map(|[r, g, b, a]| {
+            if a == 0 {
+                return [r, g, b, a];
+            }
+            let index = from_colors
+                .iter()
+                .position(|color| *color == [r, g, b])
+                .or_else(|| from.nearest(Color::rgb_u8(r, g, b)));
+            match index {
+                Some(index) if index < to.colors.len() => {
+                    let [r, g, b, _] = rgba_u8(to.colors[index]);
+                    [r, g, b, a]
+                }
+                _ => [r, g, b, a],
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut swapped = image.clone();
+    set_rgba_pixels(&mut swapped, &pixels).then_some(swapped)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+    use super::*;
+
+    #[test]
+    fn colors_are_swapped_by_index() {
+        let from = PixelPalette::new(vec![Color::rgb_u8(255, 0, 0), Color::rgb_u8(0, 0, 255)]);
+        let to = PixelPalette::new(vec![Color::rgb_u8(0, 255, 0)]);
+        let image = Image::new(
+            Extent3d {
+                width: 4,
+                height: 1,
+                ..default()
+            },
+            TextureDimension::D2,
+            [
+                [255, 0, 0, 255],
+                [250, 5, 0, 128],
+                [0, 0, 255, 255],
+                [255, 0, 0, 0],
+            ]
+            .concat(),
+            TextureFormat::Rgba8UnormSrgb,
+        );
+        let swapped = swap_image(&image, &from, &to).unwrap();
+        assert_eq!(
+            rgba_pixels(&swapped).unwrap(),
+            vec![
+                [0, 255, 0, 255],
+                [0, 255, 0, 128],
+                // Past the end of `to`
+                [0, 0, 255, 255],
+                [255, 0, 0, 0],
+            ]
+        );
+    }
+}
@@ -0,0 +1,1227 @@
+use bevy::{
+    asset::HandleId,
+    core_pipeline::clear_color::ClearColorConfig,
+    ecs::system::SystemParam,
+    prelude::*,
+    reflect::TypeUuid,
+    render::{render_resource::TextureFormat, texture::DEFAULT_IMAGE_HANDLE},
+    sprite::Mesh2dHandle,
+    utils::{HashMap, HashSet},
+};
+
+use crate::alpha::{plugin::AlphaThresholds, system::threshold_image};
+use crate::layers::system::{closest_layer, PixelLayer};
+
+use super::{
+    cycle::PaletteCycle,
+    daynight::{blend_palettes, surrounding_keyframes},
+    fade::{FadeLevel, FadeTarget},
+    indexed::{palette_texture, IndexedImage, IndexedSprite, PaletteMaterial},
+    plugin::{
+        ActivePalette, DayNightCycle, IndexedTexture, IndexedTextures, LayerPalettes,
+        PaletteDisplayChanged, PaletteDisplays, PaletteFade, PaletteFadeFinished, PaletteSwapCache,
+        QuantizedImages,
+    },
+    quantize::quantize_image,
+    swap::{swap_image, PaletteSwap, PaletteSwapSource},
+};
+
+/// Converts a color to OKLab so distances between colors match how different they look
+pub fn oklab(color: Color) -> Vec3 {
+    let [r, g, b, _] = color.as_linear_rgba_f32();
+    let l = (0.41222147 * r + 0.53633254 * g + 0.05144599 * b).cbrt();
+    let m = (0.2119035 * r + 0.6806995 * g + 0.10739696 * b).cbrt();
+    let s = (0.08830246 * r + 0.28171884 * g + 0.6299787 * b).cbrt();
+    Vec3::new(
+        0.21045426 * l + 0.7936178 * m - 0.004072047 * s,
+        1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+        0.025904037 * l + 0.78277177 * m - 0.80867577 * s,
+    )
+}
+
+/// The pixels of an 8 bit rgba image in the order they are stored. Returns None for any other format
+pub fn rgba_pixels(image: &Image) -> Option<Vec<[u8; 4]>> {
+    match image.texture_descriptor.format {
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => Some(
+            image
+                .data
+                .chunks_exact(4)
+                .map(|pixel| [pixel[0], pixel[1], pixel[2], pixel[3]])
+                .collect(),
+        ),
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => Some(
+            image
+                .data
+                .chunks_exact(4)
+                .map(|pixel| [pixel[2], pixel[1], pixel[0], pixel[3]])
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// Writes pixels from [`rgba_pixels`] back into the image. Returns false if the format or pixel count doesn't match
+pub fn set_rgba_pixels(image: &mut Image, pixels: &[[u8; 4]]) -> bool {
+    if pixels.len() * 4 != image.data.len() {
+        return false;
+    }
+    let bgra = match image.texture_descriptor.format {
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => false,
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => true,
+        _ => return false,
+    };
+    for (data, [r, g, b, a]) in image.data.chunks_exact_mut(4).zip(pixels) {
+        if bgra {
+            data.copy_from_slice(&[*b, *g, *r, *a]);
+        } else {
+            data.copy_from_slice(&[*r, *g, *b, *a]);
+        }
+    }
+    true
+}
+
+/// A limited set of colors. Colors are matched to their closest palette entry in OKLab space
+#[derive(Debug, Default, Clone, TypeUuid)]
+#[uuid = "21c666d7-6c7e-46a2-87ca-ee8586007442"]
+pub struct PixelPalette {
+    pub colors: Vec<Color>,
+    /// Ranges of indices that rotate their colors over time
+    pub cycles: Vec<PaletteCycle>,
+}
+
+impl PixelPalette {
+    pub fn new(colors: Vec<Color>) -> Self {
+        Self {
+            colors,
+            cycles: Vec::new(),
+        }
+    }
+
+    pub fn with_cycle(mut self, cycle: PaletteCycle) -> Self {
+        self.cycles.push(cycle);
+        self
+    }
+
+    /// The index of the palette color closest to `color`. Alpha is ignored
+    pub fn nearest(&self, color: Color) -> Option<usize> {
+        let target = oklab(color);
+        self.colors
+            .iter()
+            .map(|color| oklab(*color).distance_squared(target))
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(index, _)| index)
+    }
+
+    /// The color that is shown for an index, after any cycles have moved it and any fade is applied
+    pub fn display(&self, index: usize, display: Option<&PaletteDisplay>) -> Color {
+        let steps = display.map_or(&[][..], |display| &display.cycle_steps);
+        let source = self
+            .cycles
+            .iter()
+            .enumerate()
+            .find_map(|(cycle_index, cycle)| {
+                let step = steps.get(cycle_index).copied().unwrap_or(0);
+                cycle.source(index, step, self.colors.len())
+            })
+            .unwrap_or(index);
+        let color = display
+            .and_then(|display| display.colors.as_ref())
+            .and_then(|colors| colors.get(source))
+            .or(self.colors.get(source))
+            .copied()
+            .unwrap_or(Color::NONE);
+        match display.and_then(|display| display.fade.as_ref()) {
+            Some(fade) => {
+                let faded = fade.apply(index, color);
+                match fade.snap.then(|| self.nearest(faded)).flatten() {
+                    Some(nearest) => self.colors[nearest].with_a(faded.a()),
+                    None => faded,
+                }
+            }
+            None => color,
+        }
+    }
+
+    /// The closest palette color to `color`, keeping its alpha. This ignores cycles since it's used to bake images
+    pub fn snap(&self, color: Color) -> Color {
+        match self.nearest(color) {
+            Some(index) => self.colors[index].with_a(color.a()),
+            None => color,
+        }
+    }
+
+    /// Maps a color that may have already been mapped before. If the color is still what was written last time the
+    /// original color is mapped again instead so switching palettes doesn't pile up rounding
+    pub fn remap(
+        &self,
+        current: Color,
+        previous: Option<MappedColor>,
+        display: Option<&PaletteDisplay>,
+    ) -> Option<MappedColor> {
+        let original = match previous {
+            Some(previous) if previous.mapped == current => previous.original,
+            _ => current,
+        };
+        let index = self.nearest(original)?;
+        Some(MappedColor {
+            original,
+            index,
+            mapped: self.display(index, display).with_a(original.a()),
+        })
+    }
+
+    /// Updates a mapped color after only the way the palette is shown changed. It keeps the index it was matched to
+    /// unless something else changed the color since, then it's mapped again
+    pub fn redisplay(
+        &self,
+        current: Color,
+        previous: Option<MappedColor>,
+        display: Option<&PaletteDisplay>,
+    ) -> Option<MappedColor> {
+        match previous {
+            Some(previous) if previous.mapped == current => Some(MappedColor {
+                mapped: self
+                    .display(previous.index, display)
+                    .with_a(previous.original.a()),
+                ..previous
+            }),
+            _ => self.remap(current, previous, display),
+        }
+    }
+}
+
+/// How a palette is shown right now. Cycles, fades and the day night blend only change this and not the palette
+/// asset, so animating a palette doesn't make images get quantized, swapped or checked again
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PaletteDisplay {
+    /// How many steps each cycle of the palette has taken
+    pub cycle_steps: Vec<usize>,
+    /// Shown in place of the palette colors index by index, set by [`super::plugin::DayNightCycle`]
+    pub colors: Option<Vec<Color>>,
+    /// Set by [`super::plugin::PaletteFade`] while the palette is faded
+    pub fade: Option<FadeLevel>,
+}
+
+/// What a color was before being mapped to the palette and what it was mapped to
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MappedColor {
+    pub original: Color,
+    pub index: usize,
+    pub mapped: Color,
+}
+
+/// Remembers the original color of a sprite that was mapped to the palette
+#[derive(Component, Debug, Copy, Clone, Deref, DerefMut)]
+pub struct SpriteColorMapping(pub MappedColor);
+
+/// Remembers the original clear color of a camera that was mapped to the palette
+#[derive(Component, Debug, Copy, Clone, Deref, DerefMut)]
+pub struct ClearColorMapping(pub MappedColor);
+
+/// White tints on textured sprites don't change anything so they are left alone
+fn is_plain_tint(color: Color, textured: bool) -> bool {
+    textured && color.as_rgba_f32()[..3] == [1.0, 1.0, 1.0]
+}
+
+/// Palette changes since last time. Changes to the palette assets themselves need colors matched again, while
+/// changes to how they are shown only need the shown colors updated
+#[derive(SystemParam)]
+pub struct PaletteChanges<'w, 's> {
+    pub displays: Res<'w, PaletteDisplays>,
+    palette_events: EventReader<'w, 's, AssetEvent<PixelPalette>>,
+    display_events: EventReader<'w, 's, PaletteDisplayChanged>,
+}
+
+impl<'w, 's> PaletteChanges<'w, 's> {
+    /// The ids of the palettes that were loaded or changed and of the ones that are only shown differently
+    pub fn read(&mut self) -> (HashSet<HandleId>, HashSet<HandleId>) {
+        let modified = self
+            .palette_events
+            .iter()
+            .filter_map(|event| match event {
+                AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                    Some(handle.id())
+                }
+                AssetEvent::Removed { .. } => None,
+            })
+            .collect::<HashSet<_>>();
+        let redisplayed = self
+            .display_events
+            .iter()
+            .map(|event| event.palette)
+            .filter(|palette| !modified.contains(palette))
+            .collect();
+        (modified, redisplayed)
+    }
+
+    pub fn display(&self, palette: &Handle<PixelPalette>) -> Option<&PaletteDisplay> {
+        self.displays.palettes.get(&palette.id())
+    }
+}
+
+/// Finds the palette a sprite maps to from the [`LayerPalettes`] of its layer, falling back to the active palette
+#[derive(SystemParam)]
+pub struct SpritePalettes<'w, 's> {
+    pub active: Res<'w, ActivePalette>,
+    pub layers: Res<'w, LayerPalettes>,
+    pub palettes: Res<'w, Assets<PixelPalette>>,
+    layer_query: Query<'w, 's, &'static PixelLayer>,
+    parent_query: Query<'w, 's, &'static Parent>,
+}
+
+impl<'w, 's> SpritePalettes<'w, 's> {
+    pub fn handle(&self, entity: Entity) -> &Handle<PixelPalette> {
+        closest_layer(entity, &self.layer_query, &self.parent_query)
+            .and_then(|layer| self.layers.get(layer))
+            .unwrap_or(&self.active.palette)
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<&PixelPalette> {
+        self.palettes.get(self.handle(entity))
+    }
+
+    /// Whether the palettes were switched so every sprite has to be mapped again
+    pub fn changed(&self) -> bool {
+        self.active.is_changed() || self.layers.is_changed()
+    }
+}
+
+/// Maps the color of every sprite to the palette of its layer. When a palette is only shown differently the sprites
+/// keep the index they were matched to and just get its new color
+#[allow(clippy::type_complexity)]
+pub fn snap_sprite_colors(
+    mut commands: Commands,
+    sprite_palettes: SpritePalettes,
+    mut changes: PaletteChanges,
+    mut sprite_query: Query<(
+        Entity,
+        &mut Sprite,
+        &Handle<Image>,
+        Option<&mut SpriteColorMapping>,
+    )>,
+) {
+    let (modified, redisplayed) = changes.read();
+    let changed = sprite_palettes.changed();
+    for (entity, mut sprite, image, mapping) in sprite_query.iter_mut() {
+        let handle = sprite_palettes.handle(entity);
+        let remap = changed || sprite.is_changed() || modified.contains(&handle.id());
+        if !remap && !redisplayed.contains(&handle.id()) {
+            continue;
+        }
+        let Some(palette) = sprite_palettes.palettes.get(handle) else {
+            continue;
+        };
+        let previous = mapping.as_deref().map(|mapping| mapping.0);
+        if previous.is_none()
+            && is_plain_tint(sprite.color, image.id() != DEFAULT_IMAGE_HANDLE.id())
+        {
+            continue;
+        }
+        let display = changes.display(handle);
+        let mapped = match remap {
+            true => palette.remap(sprite.color, previous, display),
+            false => palette.redisplay(sprite.color, previous, display),
+        };
+        if let Some(mapped) = mapped {
+            if sprite.color != mapped.mapped {
+                sprite.color = mapped.mapped;
+            }
+            match mapping {
+                Some(mut mapping) => mapping.0 = mapped,
+                None => {
+                    commands.entity(entity).insert(SpriteColorMapping(mapped));
+                }
+            }
+        }
+    }
+}
+
+/// Maps the color of every ColorMaterial to the active palette
+pub fn snap_material_colors(
+    active: Res<ActivePalette>,
+    palettes: Res<Assets<PixelPalette>>,
+    mut changes: PaletteChanges,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut material_events: EventReader<AssetEvent<ColorMaterial>>,
+    mut mappings: Local<HashMap<HandleId, MappedColor>>,
+) {
+    let (modified, redisplayed) = changes.read();
+    let Some(palette) = palettes.get(&active.palette) else {
+        return;
+    };
+    let remap = modified.contains(&active.palette.id()) || active.is_changed();
+    let mut changed_ids = HashSet::new();
+    for event in material_events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                changed_ids.insert(handle.id());
+            }
+            AssetEvent::Removed { handle } => {
+                mappings.remove(&handle.id());
+            }
+        }
+    }
+    let ids = if remap || redisplayed.contains(&active.palette.id()) {
+        materials.ids().collect::<Vec<_>>()
+    } else {
+        changed_ids.iter().copied().collect()
+    };
+    let display = changes.display(&active.palette);
+    for id in ids {
+        let current = match materials.get(&Handle::weak(id)) {
+            Some(material) => material,
+            None => continue,
+        };
+        let previous = mappings.get(&id).copied();
+        if previous.is_none() && is_plain_tint(current.color, current.texture.is_some()) {
+            continue;
+        }
+        let mapped = match remap || changed_ids.contains(&id) {
+            true => palette.remap(current.color, previous, display),
+            false => palette.redisplay(current.color, previous, display),
+        };
+        if let Some(mapped) = mapped {
+            if current.color != mapped.mapped {
+                if let Some(material) = materials.get_mut(&Handle::weak(id)) {
+                    material.color = mapped.mapped;
+                }
+            }
+            mappings.insert(id, mapped);
+        }
+    }
+}
+
+/// Maps the clear color and the clear color of every 2d camera to the active palette
+pub fn snap_clear_colors(
+    mut commands: Commands,
+    active: Res<ActivePalette>,
+    palettes: Res<Assets<PixelPalette>>,
+    mut changes: PaletteChanges,
+    mut clear_color: ResMut<ClearColor>,
+    mut camera_query: Query<(Entity, &mut Camera2d, Option<&mut ClearColorMapping>)>,
+    mut clear_mapping: Local<Option<MappedColor>>,
+) {
+    let (modified, redisplayed) = changes.read();
+    let Some(palette) = palettes.get(&active.palette) else {
+        return;
+    };
+    let remap = modified.contains(&active.palette.id()) || active.is_changed();
+    let redisplay = redisplayed.contains(&active.palette.id());
+    let display = changes.display(&active.palette);
+    let refresh = |current, previous, remap| match remap {
+        true => palette.remap(current, previous, display),
+        false => palette.redisplay(current, previous, display),
+    };
+    if remap || redisplay || clear_color.is_changed() {
+        if let Some(mapped) = refresh(
+            clear_color.0,
+            *clear_mapping,
+            remap || clear_color.is_changed(),
+        ) {
+            if clear_color.0 != mapped.mapped {
+                clear_color.0 = mapped.mapped;
+            }
+            *clear_mapping = Some(mapped);
+        }
+    }
+    for (entity, mut camera_2d, mapping) in camera_query.iter_mut() {
+        if !remap && !redisplay && !camera_2d.is_changed() {
+            continue;
+        }
+        if let ClearColorConfig::Custom(color) = camera_2d.clear_color {
+            let previous = mapping.as_deref().map(|mapping| mapping.0);
+            if let Some(mapped) = refresh(color, previous, remap || camera_2d.is_changed()) {
+                if color != mapped.mapped {
+                    camera_2d.clear_color = ClearColorConfig::Custom(mapped.mapped);
+                }
+                match mapping {
+                    Some(mut mapping) => mapping.0 = mapped,
+                    None => {
+                        commands.entity(entity).insert(ClearColorMapping(mapped));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Counts off one of our own writes to an image, which send a modified event like any other change. Returns false
+/// when the event came from someone else. Counting matters since a single frame can hold several of our events
+pub fn own_write(written: &mut HashMap<HandleId, u32>, id: HandleId) -> bool {
+    match written.get_mut(&id) {
+        Some(count) => {
+            *count -= 1;
+            if *count == 0 {
+                written.remove(&id);
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// Bookkeeping for [`quantize_images`]. `written` counts the writes we made whose modified event is still to come
+#[derive(Default)]
+pub struct QuantizeState {
+    pub originals: HashMap<HandleId, Vec<u8>>,
+    pub done: HashSet<HandleId>,
+    pub written: HashMap<HandleId, u32>,
+}
+
+/// Remaps the images in [`QuantizedImages`] to their palette once both are loaded. The original pixels are kept around
+/// so the image can be remapped again when the palette changes or the image is reloaded. Images with an
+/// [`AlphaThreshold`] get it applied here first, from the original pixels, so the two passes never work on each
+/// other's output
+pub fn quantize_images(
+    quantized: Res<QuantizedImages>,
+    thresholds: Option<Res<AlphaThresholds>>,
+    palettes: Res<Assets<PixelPalette>>,
+    mut images: ResMut<Assets<Image>>,
+    mut image_events: EventReader<AssetEvent<Image>>,
+    mut palette_events: EventReader<AssetEvent<PixelPalette>>,
+    mut state: Local<QuantizeState>,
+) {
+    let QuantizeState {
+        originals,
+        done,
+        written,
+    } = &mut *state;
+    for event in image_events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                // Our own writes also send an event which shouldn't trigger another pass
+                if !own_write(written, handle.id()) {
+                    originals.remove(&handle.id());
+                    done.remove(&handle.id());
+                }
+            }
+            AssetEvent::Removed { handle } => {
+                originals.remove(&handle.id());
+                done.remove(&handle.id());
+                written.remove(&handle.id());
+            }
+        }
+    }
+    // Images can only have been quantized with a palette that was already there, so creating one changes nothing
+    for event in palette_events.iter() {
+        if let AssetEvent::Modified { handle } | AssetEvent::Removed { handle } = event {
+            for (image, settings) in quantized.images.iter() {
+                if settings.palette == *handle {
+                    done.remove(&image.id());
+                }
+            }
+        }
+    }
+    if thresholds
+        .as_ref()
+        .is_some_and(|thresholds| thresholds.is_changed())
+    {
+        done.clear();
+    }
+
+    for (handle, settings) in quantized.images.iter() {
+        if done.contains(&handle.id()) {
+            continue;
+        }
+        // get_mut marks the image as modified so it's only called once both assets are there
+        let Some(palette) = palettes.get(&settings.palette) else {
+            continue;
+        };
+        if !images.contains(handle) {
+            continue;
+        }
+        if let Some(image) = images.get_mut(handle) {
+            let original = originals
+                .entry(handle.id())
+                .or_insert_with(|| image.data.clone());
+            image.data.clone_from(original);
+            let threshold = thresholds.as_ref().and_then(|thresholds| {
+                thresholds.images.get(handle).copied().or(thresholds.global)
+            });
+            if let Some(threshold) = threshold {
+                threshold_image(image, threshold);
+            }
+            if !quantize_image(image, palette, settings.dither) {
+                warn!(
+                    "Image {:?} can't be quantized since it's a {:?} image",
+                    handle.id(),
+                    image.texture_descriptor.format
+                );
+            }
+            *written.entry(handle.id()).or_default() += 1;
+            done.insert(handle.id());
+        }
+    }
+}
+
+/// Makes the index texture, material and mesh for every indexed image and remakes them when the image changes
+pub fn prepare_indexed_images(
+    indexed_images: Res<Assets<IndexedImage>>,
+    (palettes, displays): (Res<Assets<PixelPalette>>, Res<PaletteDisplays>),
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<PaletteMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut textures: ResMut<IndexedTextures>,
+    mut indexed_events: EventReader<AssetEvent<IndexedImage>>,
+) {
+    for event in indexed_events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                if let Some(indexed) = indexed_images.get(handle) {
+                    let palette = textures
+                        .palettes
+                        .entry(indexed.palette.id())
+                        .or_insert_with(|| {
+                            images.add(palette_texture(
+                                palettes
+                                    .get(&indexed.palette)
+                                    .unwrap_or(&PixelPalette::default()),
+                                displays.palettes.get(&indexed.palette.id()),
+                            ))
+                        })
+                        .clone();
+                    let mesh = Mesh::from(shape::Quad::new(indexed.size.as_vec2()));
+                    match textures.images.get(&handle.id()) {
+                        Some(texture) => {
+                            if let Some(image) = images.get_mut(&texture.indices) {
+                                *image = indexed.index_texture();
+                            }
+                            if let Some(material) = materials.get_mut(&texture.material) {
+                                material.palette = palette;
+                            }
+                            if let Some(old_mesh) = meshes.get_mut(&texture.mesh) {
+                                *old_mesh = mesh;
+                            }
+                        }
+                        None => {
+                            let indices = images.add(indexed.index_texture());
+                            let texture = IndexedTexture {
+                                material: materials.add(PaletteMaterial {
+                                    indices: indices.clone(),
+                                    palette,
+                                }),
+                                indices,
+                                mesh: meshes.add(mesh),
+                            };
+                            textures.images.insert(handle.id(), texture);
+                        }
+                    }
+                }
+            }
+            AssetEvent::Removed { handle } => {
+                textures.images.remove(&handle.id());
+            }
+        }
+    }
+}
+
+/// Redraws the palette texture whenever a palette used by indexed images changes or is shown differently
+pub fn update_palette_textures(
+    palettes: Res<Assets<PixelPalette>>,
+    textures: Res<IndexedTextures>,
+    mut images: ResMut<Assets<Image>>,
+    mut changes: PaletteChanges,
+) {
+    let (modified, redisplayed) = changes.read();
+    for id in modified.union(&redisplayed) {
+        let handle = Handle::weak(*id);
+        if let (Some(palette), Some(texture)) = (palettes.get(&handle), textures.palettes.get(id)) {
+            if let Some(image) = images.get_mut(texture) {
+                *image = palette_texture(palette, changes.display(&handle));
+            }
+        }
+    }
+}
+
+/// Gives indexed sprites the mesh and material of their image once it is ready
+#[allow(clippy::type_complexity)]
+pub fn spawn_indexed_sprites(
+    mut commands: Commands,
+    textures: Res<IndexedTextures>,
+    sprite_query: Query<
+        (Entity, &IndexedSprite),
+        Or<(Changed<IndexedSprite>, Without<Handle<PaletteMaterial>>)>,
+    >,
+) {
+    for (entity, sprite) in sprite_query.iter() {
+        if let Some(texture) = textures.images.get(&sprite.image.id()) {
+            commands
+                .entity(entity)
+                .insert((Mesh2dHandle(texture.mesh.clone()), texture.material.clone()));
+        }
+    }
+}
+
+/// Swaps the image of sprites with a [`PaletteSwap`] for a recolored copy, reusing copies that were already made.
+/// Giving a swapped sprite a new image swaps that one instead. Sprites get their original image back once the swap
+/// is removed
+#[allow(clippy::type_complexity)]
+pub fn apply_palette_swaps(
+    mut commands: Commands,
+    mut swap_query: Query<
+        (
+            Entity,
+            &PaletteSwap,
+            &mut Handle<Image>,
+            Option<&mut PaletteSwapSource>,
+        ),
+        Or<(
+            Changed<PaletteSwap>,
+            Changed<Handle<Image>>,
+            Without<PaletteSwapSource>,
+        )>,
+    >,
+    mut restore_query: Query<(&PaletteSwapSource, &mut Handle<Image>), Without<PaletteSwap>>,
+    mut removed: RemovedComponents<PaletteSwap>,
+    mut cache: ResMut<PaletteSwapCache>,
+    mut images: ResMut<Assets<Image>>,
+    palettes: Res<Assets<PixelPalette>>,
+) {
+    for entity in removed.iter() {
+        if let Ok((source, mut image)) = restore_query.get_mut(entity) {
+            if *image == source.swapped {
+                *image = source.original.clone();
+            }
+            commands.entity(entity).remove::<PaletteSwapSource>();
+        }
+    }
+
+    for (entity, swap, mut image, source) in swap_query.iter_mut() {
+        let original = match source.as_deref() {
+            Some(source) if *image == source.swapped => source.original.clone(),
+            _ => image.clone(),
+        };
+        let key = (original.id(), swap.from.id(), swap.to.id());
+        let cached = cache
+            .images
+            .get(&key)
+            .filter(|swapped| images.contains(*swapped));
+        // The cache only has weak handles, the sprite needs a strong one to keep the image alive
+        let swapped = match cached {
+            Some(swapped) => images.get_handle(swapped),
+            None => {
+                let swapped = match (
+                    images.get(&original),
+                    palettes.get(&swap.from),
+                    palettes.get(&swap.to),
+                ) {
+                    (Some(original), Some(from), Some(to)) => swap_image(original, from, to),
+                    // Try again next frame once everything is loaded
+                    _ => continue,
+                };
+                match swapped {
+                    Some(swapped) => {
+                        let swapped = images.add(swapped);
+                        cache.images.insert(key, swapped.clone_weak());
+                        swapped
+                    }
+                    None => {
+                        warn!(
+                            "Image {:?} can't be palette swapped since it isn't 8 bit rgba",
+                            original.id()
+                        );
+                        original.clone()
+                    }
+                }
+            }
+        };
+        if *image != swapped {
+            *image = swapped.clone();
+        }
+        match source {
+            Some(mut source) => {
+                if source.original != original || source.swapped != swapped {
+                    *source = PaletteSwapSource {
+                        original,
+                        swapped: swapped.clone_weak(),
+                    };
+                }
+            }
+            None => {
+                commands.entity(entity).insert(PaletteSwapSource {
+                    original,
+                    swapped: swapped.clone_weak(),
+                });
+            }
+        }
+    }
+}
+
+/// Remakes swapped images in place when their original image or either palette changes and forgets the ones that
+/// were removed
+pub fn refresh_palette_swaps(
+    mut cache: ResMut<PaletteSwapCache>,
+    mut images: ResMut<Assets<Image>>,
+    palettes: Res<Assets<PixelPalette>>,
+    mut image_events: EventReader<AssetEvent<Image>>,
+    mut palette_events: EventReader<AssetEvent<PixelPalette>>,
+) {
+    let mut changed = HashSet::new();
+    let mut removed = HashSet::new();
+    for event in image_events.iter() {
+        match event {
+            AssetEvent::Modified { handle } => {
+                changed.insert(handle.id());
+            }
+            AssetEvent::Removed { handle } => {
+                removed.insert(handle.id());
+            }
+            AssetEvent::Created { .. } => (),
+        }
+    }
+    for event in palette_events.iter() {
+        match event {
+            AssetEvent::Modified { handle } => {
+                changed.insert(handle.id());
+            }
+            AssetEvent::Removed { handle } => {
+                removed.insert(handle.id());
+            }
+            AssetEvent::Created { .. } => (),
+        }
+    }
+    if !removed.is_empty() {
+        cache.images.retain(|(original, from, to), swapped| {
+            ![*original, *from, *to, swapped.id()]
+                .iter()
+                .any(|id| removed.contains(id))
+        });
+    }
+    if changed.is_empty() {
+        return;
+    }
+    for ((original, from, to), swapped) in cache.images.iter() {
+        if !changed.contains(original) && !changed.contains(from) && !changed.contains(to) {
+            continue;
+        }
+        let remade = match (
+            images.get(&Handle::weak(*original)),
+            palettes.get(&Handle::weak(*from)),
+            palettes.get(&Handle::weak(*to)),
+        ) {
+            (Some(original), Some(from), Some(to)) => swap_image(original, from, to),
+            _ => None,
+        };
+        // get_mut marks the swapped image as modified so only call it with something to write
+        let Some(remade) = remade else {
+            continue;
+        };
+        if let Some(image) = images.get_mut(swapped) {
+            *image = remade;
+        }
+    }
+}
+
+/// Steps palette cycles by whole indices using the time since the last step, so they run at the same speed at any
+/// frame rate. Only the [`PaletteDisplays`] are changed, on frames where a cycle actually moved
+pub fn cycle_palettes(
+    time: Res<Time>,
+    palettes: Res<Assets<PixelPalette>>,
+    mut displays: ResMut<PaletteDisplays>,
+    mut palette_events: EventReader<AssetEvent<PixelPalette>>,
+    mut display_events: EventWriter<PaletteDisplayChanged>,
+    mut elapsed: Local<HashMap<(HandleId, usize), f32>>,
+) {
+    for event in palette_events.iter() {
+        if let AssetEvent::Removed { handle } = event {
+            displays.palettes.remove(&handle.id());
+            elapsed.retain(|(id, _), _| *id != handle.id());
+        }
+    }
+    let delta = time.delta_seconds();
+    for (id, palette) in palettes.iter() {
+        let mut moved = false;
+        for (index, cycle) in palette.cycles.iter().enumerate() {
+            let len = cycle.indices(palette.colors.len()).len();
+            if cycle.speed <= 0.0 || len == 0 {
+                continue;
+            }
+            let elapsed = elapsed.entry((id, index)).or_default();
+            *elapsed += delta;
+            let count = (*elapsed * cycle.speed).floor();
+            if count >= 1.0 {
+                *elapsed -= count / cycle.speed;
+                let steps = &mut displays.palettes.entry(id).or_default().cycle_steps;
+                if steps.len() < palette.cycles.len() {
+                    steps.resize(palette.cycles.len(), 0);
+                }
+                steps[index] = (steps[index] + count as usize) % len;
+                moved = true;
+            }
+        }
+        if moved {
+            display_events.send(PaletteDisplayChanged { palette: id });
+        }
+    }
+}
+
+/// Steps the [`PaletteFade`] and shows its level on the active palette and every palette in [`LayerPalettes`], which
+/// includes the one of the [`DayNightCycle`]. Only the [`PaletteDisplays`] change and only when the level moves
+pub fn fade_palettes(
+    time: Res<Time>,
+    fade: Option<ResMut<PaletteFade>>,
+    sprite_palettes: SpritePalettes,
+    mut displays: ResMut<PaletteDisplays>,
+    mut finished: EventWriter<PaletteFadeFinished>,
+    mut display_events: EventWriter<PaletteDisplayChanged>,
+    mut faded: Local<HashSet<HandleId>>,
+) {
+    let targeted = match fade {
+        Some(_) => std::iter::once(&sprite_palettes.active.palette)
+            .chain(sprite_palettes.layers.iter())
+            .map(|handle| handle.id())
+            .collect::<HashSet<_>>(),
+        None => HashSet::new(),
+    };
+    // Put back palettes that aren't faded anymore
+    for id in faded.drain_filter(|id| !targeted.contains(id)) {
+        if let Some(display) = displays.palettes.get_mut(&id) {
+            display.fade = None;
+            display_events.send(PaletteDisplayChanged { palette: id });
+        }
+    }
+    let Some(mut fade) = fade else {
+        return;
+    };
+    let targets = match &fade.target {
+        FadeTarget::Black => vec![Color::BLACK],
+        FadeTarget::White => vec![Color::WHITE],
+        FadeTarget::Palette(handle) => match sprite_palettes.palettes.get(handle) {
+            Some(target) => target.colors.clone(),
+            None => return,
+        },
+    };
+    if !fade.finished() {
+        if fade.step_time <= 0.0 {
+            fade.step = fade.steps;
+        } else {
+            fade.elapsed += time.delta_seconds();
+            let steps = (fade.elapsed / fade.step_time).floor();
+            fade.elapsed -= steps * fade.step_time;
+            fade.step = (fade.step + steps as u32).min(fade.steps);
+        }
+    }
+    let level = match fade.fade_in && fade.finished() {
+        true => None,
+        false => Some(FadeLevel {
+            targets,
+            amount: fade.amount(),
+            snap: fade.snap,
+        }),
+    };
+    for id in targeted {
+        let current = displays
+            .palettes
+            .get(&id)
+            .and_then(|display| display.fade.as_ref());
+        if current != level.as_ref() {
+            displays.palettes.entry(id).or_default().fade = level.clone();
+            display_events.send(PaletteDisplayChanged { palette: id });
+        }
+        faded.insert(id);
+    }
+    // Whether it was reported is only bookkeeping, a finished fade shouldn't show up as changed every frame
+    if fade.bypass_change_detection().report() {
+        finished.send(PaletteFadeFinished {
+            target: fade.target.clone(),
+            fade_in: fade.fade_in,
+        });
+    }
+}
+
+/// Advances the [`DayNightCycle`] and shows the blend on its output palette. The output palette holds the colors of
+/// the first keyframe so sprites are matched against those once, the blend only changes the shown colors in
+/// [`PaletteDisplays`]. Its layers are pointed at the output in [`LayerPalettes`] and handed back with the palette
+/// they had before once the cycle is removed or they are taken out of it
+#[allow(clippy::type_complexity)]
+pub fn cycle_day_night(
+    time: Res<Time>,
+    cycle: Option<ResMut<DayNightCycle>>,
+    mut layer_palettes: ResMut<LayerPalettes>,
+    mut palettes: ResMut<Assets<PixelPalette>>,
+    mut displays: ResMut<PaletteDisplays>,
+    mut display_events: EventWriter<PaletteDisplayChanged>,
+    mut inserted: Local<HashMap<PixelLayer, (Handle<PixelPalette>, Option<Handle<PixelPalette>>)>>,
+) {
+    inserted.retain(|layer, (output, previous)| {
+        let kept = cycle.as_ref().is_some_and(|cycle| {
+            cycle.layers.contains(layer) && cycle.output.as_ref() == Some(output)
+        });
+        if !kept && layer_palettes.layers.get(layer) == Some(output) {
+            match previous.take() {
+                Some(previous) => layer_palettes.layers.insert(*layer, previous),
+                None => layer_palettes.layers.remove(layer),
+            };
+        }
+        kept
+    });
+    let Some(mut cycle) = cycle else {
+        return;
+    };
+    if cycle.day_length > 0.0 {
+        cycle.time = (cycle.time + time.delta_seconds() / cycle.day_length).rem_euclid(1.0);
+    }
+    let output = match &cycle.output {
+        Some(output) => output.clone(),
+        None => {
+            let output = palettes.add(PixelPalette::default());
+            cycle.output = Some(output.clone());
+            output
+        }
+    };
+    for layer in cycle.layers.iter() {
+        let current = layer_palettes.layers.get(layer);
+        inserted.entry(*layer).or_insert_with(|| {
+            let previous = current.filter(|current| **current != output).cloned();
+            (output.clone(), previous)
+        });
+        if current != Some(&output) {
+            layer_palettes.layers.insert(*layer, output.clone());
+        }
+    }
+    let base = cycle
+        .keyframes
+        .first()
+        .and_then(|keyframe| palettes.get(&keyframe.palette))
+        .map(|palette| palette.colors.clone());
+    if let Some(base) = base {
+        if palettes.get(&output).map(|output| &output.colors) != Some(&base) {
+            if let Some(output) = palettes.get_mut(&output) {
+                output.colors = base;
+            }
+        }
+    }
+    let Some((from, to, amount)) = surrounding_keyframes(&cycle.keyframes, cycle.time) else {
+        return;
+    };
+    let (Some(from), Some(to)) = (palettes.get(&from.palette), palettes.get(&to.palette)) else {
+        return;
+    };
+    let steps = cycle.steps.max(1) as f32;
+    let amount = (amount * steps).floor() / steps;
+    let mut colors = blend_palettes(from, to, amount);
+    if let Some(snap) = cycle.snap.as_ref().and_then(|snap| palettes.get(snap)) {
+        for color in colors.iter_mut() {
+            *color = snap.snap(*color);
+        }
+    }
+    let shown = displays
+        .palettes
+        .get(&output.id())
+        .and_then(|display| display.colors.as_ref());
+    if shown != Some(&colors) {
+        displays.palettes.entry(output.id()).or_default().colors = Some(colors);
+        display_events.send(PaletteDisplayChanged {
+            palette: output.id(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::event::ManualEventReader;
+    use bevy::render::render_resource::{Extent3d, TextureDimension};
+
+    use super::super::cycle::CycleDirection;
+    use super::*;
+
+    fn cycling_palette() -> PixelPalette {
+        PixelPalette::new(vec![Color::RED, Color::GREEN, Color::BLUE])
+            .with_cycle(PaletteCycle::new(0..3, 1.0, CycleDirection::Forward))
+    }
+
+    #[test]
+    fn cycles_only_change_the_shown_color() {
+        let palette = cycling_palette();
+        let mapped = palette.remap(Color::RED, None, None).unwrap();
+        assert_eq!((mapped.index, mapped.mapped), (0, Color::RED));
+
+        let display = PaletteDisplay {
+            cycle_steps: vec![1],
+            ..default()
+        };
+        let shown = palette
+            .redisplay(mapped.mapped, Some(mapped), Some(&display))
+            .unwrap();
+        assert_eq!((shown.index, shown.mapped), (0, Color::BLUE));
+        assert_eq!(shown.original, Color::RED);
+
+        // Matching again later still goes from the original color
+        let remapped = palette
+            .remap(shown.mapped, Some(shown), Some(&display))
+            .unwrap();
+        assert_eq!(remapped, shown);
+    }
+
+    #[test]
+    fn colors_changed_by_something_else_are_matched_again() {
+        let palette = cycling_palette();
+        let mapped = palette.remap(Color::RED, None, None).unwrap();
+        let shown = palette
+            .redisplay(Color::rgb(0.0, 0.9, 0.1), Some(mapped), None)
+            .unwrap();
+        assert_eq!((shown.index, shown.mapped), (1, Color::GREEN));
+    }
+
+    #[test]
+    fn fades_are_shown_without_changing_the_palette() {
+        let palette = PixelPalette::new(vec![Color::WHITE, Color::rgb(0.5, 0.5, 0.5)]);
+        let display = PaletteDisplay {
+            fade: Some(FadeLevel {
+                targets: vec![Color::BLACK],
+                amount: 0.5,
+                snap: false,
+            }),
+            ..default()
+        };
+        assert_eq!(
+            palette.display(0, Some(&display)),
+            Color::rgb(0.5, 0.5, 0.5)
+        );
+        assert_eq!(palette.colors[0], Color::WHITE);
+
+        let snapped = PaletteDisplay {
+            fade: Some(FadeLevel {
+                targets: vec![Color::BLACK],
+                amount: 0.4,
+                snap: true,
+            }),
+            ..default()
+        };
+        assert_eq!(
+            palette.display(0, Some(&snapped)),
+            Color::rgb(0.5, 0.5, 0.5)
+        );
+    }
+
+    #[test]
+    fn blended_colors_keep_the_matched_index() {
+        let day = PixelPalette::new(vec![Color::rgb(0.9, 0.9, 0.2), Color::rgb(0.2, 0.6, 1.0)]);
+        let mapped = day.remap(Color::rgb(0.3, 0.6, 0.9), None, None).unwrap();
+        assert_eq!(mapped.index, 1);
+        // At night the sky is darker than the sun ever was but the sprite keeps showing the sky
+        let night = PaletteDisplay {
+            colors: Some(vec![Color::rgb(0.3, 0.3, 0.1), Color::rgb(0.05, 0.05, 0.2)]),
+            ..default()
+        };
+        let shown = day
+            .redisplay(mapped.mapped, Some(mapped), Some(&night))
+            .unwrap();
+        assert_eq!(
+            (shown.index, shown.mapped),
+            (1, Color::rgb(0.05, 0.05, 0.2))
+        );
+    }
+
+    #[test]
+    fn own_writes_are_counted() {
+        let id = HandleId::random::<Image>();
+        let mut written = HashMap::default();
+        *written.entry(id).or_default() += 1;
+        *written.entry(id).or_default() += 1;
+        assert!(own_write(&mut written, id));
+        assert!(own_write(&mut written, id));
+        // A third event in the same frame came from someone else
+        assert!(!own_write(&mut written, id));
+        assert!(written.is_empty());
+    }
+
+    fn asset_app() -> App {
+        let mut app = App::new();
+        app.add_plugin(TaskPoolPlugin::default())
+            .add_plugin(AssetPlugin::default())
+            .add_asset::<Image>()
+            .add_asset::<PixelPalette>();
+        app
+    }
+
+    fn pixel(color: [u8; 4]) -> Image {
+        Image::new_fill(
+            Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &color,
+            TextureFormat::Rgba8UnormSrgb,
+        )
+    }
+
+    /// How many modified events were sent for `image` since the last call
+    fn modified_count(
+        app: &App,
+        reader: &mut ManualEventReader<AssetEvent<Image>>,
+        image: &Handle<Image>,
+    ) -> usize {
+        reader
+            .iter(app.world.resource::<Events<AssetEvent<Image>>>())
+            .filter(|event| matches!(event, AssetEvent::Modified { handle } if handle == image))
+            .count()
+    }
+
+    #[test]
+    fn quantizing_waits_for_the_palette_without_touching_the_image() {
+        let mut app = asset_app();
+        app.init_resource::<QuantizedImages>()
+            .add_system(quantize_images);
+        let image = app
+            .world
+            .resource_mut::<Assets<Image>>()
+            .add(pixel([255, 0, 0, 255]));
+        let palette = Handle::<PixelPalette>::weak(HandleId::random::<PixelPalette>());
+        app.world
+            .resource_mut::<QuantizedImages>()
+            .add(image.clone(), palette.clone());
+        let mut reader = ManualEventReader::default();
+        for _ in 0..5 {
+            app.update();
+            assert_eq!(modified_count(&app, &mut reader, &image), 0);
+        }
+
+        app.world
+            .resource_mut::<Assets<PixelPalette>>()
+            .set_untracked(palette.id(), PixelPalette::new(vec![Color::BLUE]));
+        let mut modified = 0;
+        for _ in 0..3 {
+            app.update();
+            modified += modified_count(&app, &mut reader, &image);
+        }
+        assert_eq!(modified, 1);
+        let images = app.world.resource::<Assets<Image>>();
+        assert_eq!(images.get(&image).unwrap().data, [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn swaps_missing_a_palette_leave_the_swapped_image_alone() {
+        let mut app = asset_app();
+        app.init_resource::<PaletteSwapCache>()
+            .add_system(refresh_palette_swaps);
+        let mut images = app.world.resource_mut::<Assets<Image>>();
+        let original = images.add(pixel([255, 0, 0, 255]));
+        let swapped = images.add(pixel([0, 0, 255, 255]));
+        let from = app
+            .world
+            .resource_mut::<Assets<PixelPalette>>()
+            .add(PixelPalette::new(vec![Color::RED]));
+        let to = HandleId::random::<PixelPalette>();
+        app.world
+            .resource_mut::<PaletteSwapCache>()
+            .images
+            .insert((original.id(), from.id(), to), swapped.clone_weak());
+        let mut reader = ManualEventReader::default();
+        app.update();
+        modified_count(&app, &mut reader, &swapped);
+
+        app.world
+            .resource_mut::<Assets<Image>>()
+            .get_mut(&original)
+            .unwrap()
+            .data = vec![0, 255, 0, 255];
+        for _ in 0..3 {
+            app.update();
+            assert_eq!(modified_count(&app, &mut reader, &swapped), 0);
+        }
+    }
+}
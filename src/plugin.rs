@@ -1,15 +1,15 @@
 use bevy::{app::PluginGroupBuilder, prelude::*};
 
-use crate::{camera, layers};
+use crate::{alpha, camera, hardware, layers, metasprite, palette, rotation};
 
+/// All of the plugins needed for a pixel game. With a hardware profile the sprite limits of that console are also added
+/// so don't add a PixelLimPlugin yourself then. `alpha_threshold` thresholds the alpha of every loaded image, it can
+/// also be changed later through [`alpha::plugin::AlphaThresholds`]
+#[derive(Default)]
 pub struct PixelPlugins {
     pub y_sort: bool,
-}
-
-impl Default for PixelPlugins {
-    fn default() -> Self {
-        Self { y_sort: false }
-    }
+    pub hardware: Option<hardware::plugin::HardwareProfile>,
+    pub alpha_threshold: Option<alpha::plugin::AlphaThreshold>,
 }
 
 /// This component is used to mark sprites. As of right now this is only used for sprite limiting.
@@ -20,11 +20,21 @@ impl PluginGroup for PixelPlugins {
     fn build(self) -> bevy::app::PluginGroupBuilder {
         let mut group = PluginGroupBuilder::start::<Self>();
         group = group.add(camera::plugin::PixelCameraPlugin);
+        group = group.add(metasprite::plugin::PixelMetaspritePlugin);
+        group = group.add(palette::plugin::PixelPalettePlugin);
+        group = group.add(alpha::plugin::PixelAlphaPlugin {
+            global: self.alpha_threshold,
+        });
+        group = group.add(rotation::plugin::PixelRotationPlugin);
         if self.y_sort {
             group = group.add(layers::plugin::PixelLayerPlugin { y_sort: true });
         } else {
             group = group.add(layers::plugin::PixelLayerPlugin { y_sort: false });
         }
+        if let Some(profile) = self.hardware {
+            group = group.add(hardware::plugin::PixelHardwarePlugin { profile });
+            group = group.add(profile.lim_plugin());
+        }
         group
     }
 }
@@ -0,0 +1,3 @@
+pub mod plugin;
+pub mod rotsprite;
+pub mod system;
@@ -0,0 +1,21 @@
+use bevy::{asset::HandleId, prelude::*, utils::HashMap};
+
+use super::system::apply_pixel_rotations;
+
+/// The rotated images made for [`super::system::PixelRotation`], keyed by the original image, the part of it that is
+/// used, the angle step and how many steps make a full turn. The handles are weak so a rotated image goes away with
+/// the last sprite using it, its entry is dropped then too
+#[derive(Resource, Debug, Default, Clone)]
+pub struct PixelRotationCache {
+    pub images: HashMap<(HandleId, [u32; 4], u32, u32), Handle<Image>>,
+}
+
+/// Rotates sprites with a [`super::system::PixelRotation`] on the cpu so they stay crisp under the pixel cameras
+pub struct PixelRotationPlugin;
+
+impl Plugin for PixelRotationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PixelRotationCache>()
+            .add_system(apply_pixel_rotations);
+    }
+}
@@ -0,0 +1,132 @@
+use bevy::{prelude::*, render::render_resource::Extent3d};
+
+use crate::palette::system::{rgba_pixels, set_rgba_pixels};
+use crate::scale::scale3x;
+
+/// Rotates pixels counterclockwise by `angle` radians RotSprite style. The pixels are scaled up with Scale3x, rotated and
+/// sampled back down at the center of each output pixel so no new colors are made. Returns the pixels and the size of
+/// the rotated buffer, which grows to fit every corner
+pub fn rotate_pixels(
+    pixels: &[[u8; 4]],
+    width: usize,
+    height: usize,
+    angle: f32,
+) -> (Vec<[u8; 4]>, usize, usize) {
+    let scaled = scale3x(pixels, width, height);
+    let (sin, cos) = angle.sin_cos();
+    let (w, h) = (width as f32, height as f32);
+    // Rounding first keeps right angles from gaining a pixel from float error
+    let fit =
+        |a: f32, b: f32| ((a * 1000.0).round() / 1000.0 + (b * 1000.0).round() / 1000.0).ceil();
+    let out_width = fit((w * cos).abs(), (h * sin).abs()) as usize;
+    let out_height = fit((w * sin).abs(), (h * cos).abs()) as usize;
+    let mut rotated = vec![[0; 4]; out_width * out_height];
+    for y in 0..out_height {
+        for x in 0..out_width {
+            // Rotate back with y pointing up like the world does
+            let dx = x as f32 + 0.5 - out_width as f32 / 2.0;
+            let dy = -(y as f32 + 0.5 - out_height as f32 / 2.0);
+            let sx = dx * cos + dy * sin + w / 2.0;
+            let sy = -(-dx * sin + dy * cos) + h / 2.0;
+            let (ux, uy) = ((sx * 3.0).floor(), (sy * 3.0).floor());
+            if ux >= 0.0 && uy >= 0.0 && ux < w * 3.0 && uy < h * 3.0 {
+                rotated[y * out_width + x] = scaled[uy as usize * width * 3 + ux as usize];
+            }
+        }
+    }
+    (rotated, out_width, out_height)
+}
+
+/// Makes a rotated copy of an image, or just the `rect` part of it for sprite sheets. Returns None if the image isn't
+/// 8 bit rgba
+pub fn rotsprite_image(image: &Image, rect: Option<Rect>, angle: f32) -> Option<Image> {
+    let pixels = rgba_pixels(image)?;
+    let size = image.texture_descriptor.size;
+    let rect = rect
+        .unwrap_or(Rect::new(0.0, 0.0, size.width as f32, size.height as f32))
+        .intersect(Rect::new(0.0, 0.0, size.width as f32, size.height as f32));
+    let (x0, y0) = (rect.min.x as usize, rect.min.y as usize);
+    let (width, height) = (rect.width() as usize, rect.height() as usize);
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let cropped = (0..height)
+        .flat_map(|y| {
+            let start = (y0 + y) * size.width as usize + x0;
+            pixels[start..start + width].iter().copied()
+        })
+        .collect::<Vec<_>>();
+    let (rotated, width, height) = rotate_pixels(&cropped, width, height, angle);
+    let mut image = image.clone();
+    image.texture_descriptor.size = Extent3d {
+        width: width as u32,
+        height: height as u32,
+        depth_or_array_layers: 1,
+    };
+    image.data = vec![0; width * height * 4];
+    set_rgba_pixels(&mut image, &rotated).then_some(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+    use bevy::{
+        render::render_resource::{TextureDimension, TextureFormat},
+        utils::HashSet,
+    };
+
+    use super::*;
+
+    const A: [u8; 4] = [255, 0, 0, 255];
+    const B: [u8; 4] = [0, 255, 0, 255];
+    const C: [u8; 4] = [0, 0, 255, 255];
+    const D: [u8; 4] = [255, 255, 0, 255];
+
+    #[test]
+    fn right_angles_move_pixels_exactly() {
+        let pixels = [A, B, C, D, A, B];
+        assert_eq!(rotate_pixels(&pixels, 3, 2, 0.0), (pixels.to_vec(), 3, 2));
+        // Counterclockwise, so the right column ends up on top
+        assert_eq!(
+            rotate_pixels(&pixels, 3, 2, FRAC_PI_2),
+            (vec![C, B, B, A, A, D], 2, 3)
+        );
+        assert_eq!(
+            rotate_pixels(&pixels, 3, 2, PI),
+            (vec![B, A, D, C, B, A], 3, 2)
+        );
+    }
+
+    #[test]
+    fn rotating_makes_no_new_colors() {
+        let pixels = (0..64)
+            .map(|index| [A, B, C, D][(index / 8 + index % 8) % 4])
+            .collect::<Vec<_>>();
+        let (rotated, width, height) = rotate_pixels(&pixels, 8, 8, FRAC_PI_4);
+        // The buffer grows to fit the corners
+        assert_eq!((width, height), (12, 12));
+        let colors = rotated.iter().copied().collect::<HashSet<_>>();
+        assert!(colors.is_subset(&[A, B, C, D, [0; 4]].into_iter().collect()));
+        assert!(colors.contains(&A) && colors.contains(&[0; 4]));
+    }
+
+    #[test]
+    fn only_the_rect_is_rotated() {
+        let image = Image::new(
+            Extent3d {
+                width: 4,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            [A, B, C, D].concat(),
+            TextureFormat::Rgba8UnormSrgb,
+        );
+        let rotated =
+            rotsprite_image(&image, Some(Rect::new(1.0, 0.0, 3.0, 1.0)), FRAC_PI_2).unwrap();
+        assert_eq!(rotated.size(), Vec2::new(1.0, 2.0));
+        assert_eq!(rgba_pixels(&rotated).unwrap(), vec![C, B]);
+        assert!(rotsprite_image(&image, Some(Rect::new(5.0, 0.0, 6.0, 1.0)), PI).is_none());
+    }
+}
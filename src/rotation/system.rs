@@ -0,0 +1,341 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+
+use crate::alpha::system::DitherAlphaSource;
+
+use super::{plugin::PixelRotationCache, rotsprite::rotsprite_image};
+
+/// Rotates a sprite counterclockwise by `angle` radians without blurring or misaligning pixels. The angle is rounded
+/// to one of `steps` angles per full turn and each one is made once with RotSprite and cached. Use this instead of
+/// rotating the Transform
+#[derive(Component, Debug, Copy, Clone, PartialEq)]
+pub struct PixelRotation {
+    pub angle: f32,
+    pub steps: u32,
+}
+
+impl Default for PixelRotation {
+    fn default() -> Self {
+        Self {
+            angle: 0.0,
+            steps: 32,
+        }
+    }
+}
+
+impl PixelRotation {
+    pub fn new(angle: f32) -> Self {
+        Self {
+            angle,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_steps(angle: f32, steps: u32) -> Self {
+        Self { angle, steps }
+    }
+
+    /// The step the angle is rounded to
+    pub fn step(&self) -> u32 {
+        let steps = self.steps.max(1);
+        ((self.angle / TAU * steps as f32).round() as i64).rem_euclid(steps as i64) as u32
+    }
+}
+
+/// The image, rect and custom size a rotated sprite had and the rotated copy it was given, so they can be put back
+/// once the rotation is removed. The size limit of [`crate::limit::plugin::PixelLimPlugin`] checks and crops these
+/// instead of the rotated copy. `rotated_rect` and `rotated_size` are what the sprite was given along with the copy,
+/// a sprite holding anything else was changed by someone else and that becomes the new source
+#[derive(Component, Debug, Clone)]
+pub struct PixelRotationSource {
+    pub original: Handle<Image>,
+    pub rect: Option<Rect>,
+    pub custom_size: Option<Vec2>,
+    pub rotated: Handle<Image>,
+    pub rotated_rect: Option<Rect>,
+    pub rotated_size: Option<Vec2>,
+}
+
+/// Gives sprites with a [`PixelRotation`] the rotated copy of their image, making copies as needed. Sprite sheet rects
+/// are cropped out before rotating and a custom size grows along with the image, setting either on a rotated sprite
+/// picks a new part to rotate so sheet animations keep working. Copies are remade when the original image changes and
+/// sprites get their image back once the component is removed. The cache only holds weak handles so copies no sprite
+/// uses anymore are freed
+#[allow(clippy::type_complexity)]
+pub fn apply_pixel_rotations(
+    mut commands: Commands,
+    mut rotation_query: Query<(
+        Entity,
+        &PixelRotation,
+        &mut Sprite,
+        &mut Handle<Image>,
+        Option<&mut PixelRotationSource>,
+        Option<&DitherAlphaSource>,
+    )>,
+    mut restore_query: Query<
+        (&PixelRotationSource, &mut Sprite, &mut Handle<Image>),
+        Without<PixelRotation>,
+    >,
+    mut removed: RemovedComponents<PixelRotation>,
+    mut cache: ResMut<PixelRotationCache>,
+    mut images: ResMut<Assets<Image>>,
+    mut image_events: EventReader<AssetEvent<Image>>,
+) {
+    for entity in removed.iter() {
+        if let Ok((source, mut sprite, mut image)) = restore_query.get_mut(entity) {
+            *image = source.original.clone();
+            sprite.rect = source.rect;
+            sprite.custom_size = source.custom_size;
+            commands.entity(entity).remove::<PixelRotationSource>();
+        }
+    }
+    for event in image_events.iter() {
+        match event {
+            AssetEvent::Modified { handle } => {
+                cache
+                    .images
+                    .retain(|(original, ..), _| *original != handle.id());
+            }
+            AssetEvent::Removed { handle } => {
+                cache.images.retain(|(original, ..), rotated| {
+                    *original != handle.id() && rotated.id() != handle.id()
+                });
+            }
+            AssetEvent::Created { .. } => (),
+        }
+    }
+
+    for (entity, rotation, mut sprite, mut image, source, dither) in rotation_query.iter_mut() {
+        // A dithered copy of our rotated image still counts as ours
+        let current = match dither {
+            Some(dither) if *image == dither.dithered => dither.original.clone(),
+            _ => image.clone(),
+        };
+        // Whatever still matches what we gave the sprite comes from the source, anything else was set since
+        let (original, rect, custom_size) = match source.as_deref() {
+            Some(source) if current == source.rotated => (
+                source.original.clone(),
+                match sprite.rect == source.rotated_rect {
+                    true => source.rect,
+                    false => sprite.rect,
+                },
+                match sprite.custom_size == source.rotated_size {
+                    true => source.custom_size,
+                    false => sprite.custom_size,
+                },
+            ),
+            _ => (current.clone(), sprite.rect, sprite.custom_size),
+        };
+        let step = rotation.step();
+        let (rotated, rotated_rect, rotated_size) = if step == 0 {
+            (original.clone(), rect, custom_size)
+        } else {
+            let bounds = rect.map_or([0; 4], |rect| {
+                [rect.min.x, rect.min.y, rect.max.x, rect.max.y].map(|value| value as u32)
+            });
+            let key = (original.id(), bounds, step, rotation.steps);
+            let cached = cache
+                .images
+                .get(&key)
+                .filter(|rotated| images.contains(*rotated));
+            // The cache only has weak handles, the sprite needs a strong one to keep the image alive
+            let rotated = match cached {
+                Some(rotated) => images.get_handle(rotated),
+                None => {
+                    let angle = step as f32 / rotation.steps as f32 * TAU;
+                    let Some(rotated) = images
+                        .get(&original)
+                        .and_then(|image| rotsprite_image(image, rect, angle))
+                    else {
+                        if images.contains(&original) {
+                            warn!(
+                                "Image {:?} can't be rotated since it isn't 8 bit rgba",
+                                original.id()
+                            );
+                        }
+                        continue;
+                    };
+                    let rotated = images.add(rotated);
+                    cache.images.insert(key, rotated.clone_weak());
+                    rotated
+                }
+            };
+            // The rotated copy is bigger than the original so a custom size has to grow by as much
+            let source_size = rect
+                .map(|rect| rect.size())
+                .or_else(|| images.get(&original).map(|image| image.size()));
+            let (Some(source_size), Some(rotated_image)) = (source_size, images.get(&rotated))
+            else {
+                continue;
+            };
+            let scale = rotated_image.size() / source_size;
+            (rotated, None, custom_size.map(|size| size * scale))
+        };
+        if current != rotated {
+            *image = rotated.clone();
+        }
+        if sprite.rect != rotated_rect {
+            sprite.rect = rotated_rect;
+        }
+        if sprite.custom_size != rotated_size {
+            sprite.custom_size = rotated_size;
+        }
+        let new_source = PixelRotationSource {
+            original,
+            rect,
+            custom_size,
+            rotated,
+            rotated_rect,
+            rotated_size,
+        };
+        match source {
+            Some(mut source) => {
+                if source.original != new_source.original
+                    || source.rotated != new_source.rotated
+                    || source.rect != new_source.rect
+                    || source.custom_size != new_source.custom_size
+                    || source.rotated_rect != new_source.rotated_rect
+                    || source.rotated_size != new_source.rotated_size
+                {
+                    *source = new_source;
+                }
+            }
+            None => {
+                commands.entity(entity).insert(new_source);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+    use super::*;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugin(TaskPoolPlugin::default())
+            .add_plugin(AssetPlugin::default())
+            .add_asset::<Image>()
+            .init_resource::<PixelRotationCache>()
+            .add_system(apply_pixel_rotations);
+        app
+    }
+
+    /// A 4x2 sheet with two 2x2 frames, red on the left and blue on the right
+    fn sheet() -> Image {
+        Image::new(
+            Extent3d {
+                width: 4,
+                height: 2,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            [RED, RED, BLUE, BLUE, RED, RED, BLUE, BLUE].concat(),
+            TextureFormat::Rgba8UnormSrgb,
+        )
+    }
+
+    /// The opaque colors of the image a sprite is drawn with
+    fn drawn_colors(app: &App, entity: Entity) -> Vec<[u8; 4]> {
+        let handle = app.world.get::<Handle<Image>>(entity).unwrap();
+        let image = app.world.resource::<Assets<Image>>().get(handle).unwrap();
+        let mut colors = image
+            .data
+            .chunks(4)
+            .filter(|pixel| pixel[3] != 0)
+            .map(|pixel| [pixel[0], pixel[1], pixel[2], pixel[3]])
+            .collect::<Vec<_>>();
+        colors.dedup();
+        colors
+    }
+
+    #[test]
+    fn changing_the_rect_of_a_rotated_sprite_picks_a_new_frame() {
+        let mut app = app();
+        let sheet = app.world.resource_mut::<Assets<Image>>().add(sheet());
+        let sprite = app
+            .world
+            .spawn((
+                PixelRotation::with_steps(TAU / 4.0, 4),
+                Sprite {
+                    rect: Some(Rect::new(0.0, 0.0, 2.0, 2.0)),
+                    custom_size: Some(Vec2::splat(2.0)),
+                    ..default()
+                },
+                sheet.clone(),
+            ))
+            .id();
+        app.update();
+        assert_eq!(app.world.get::<Sprite>(sprite).unwrap().rect, None);
+        assert_eq!(drawn_colors(&app, sprite), [RED]);
+        let rotated_size = app.world.get::<Sprite>(sprite).unwrap().custom_size;
+
+        // Stepping a sheet animation while rotated
+        app.world.get_mut::<Sprite>(sprite).unwrap().rect = Some(Rect::new(2.0, 0.0, 4.0, 2.0));
+        app.update();
+        app.update();
+        assert_eq!(drawn_colors(&app, sprite), [BLUE]);
+        let source = app.world.get::<PixelRotationSource>(sprite).unwrap();
+        assert_eq!(source.rect, Some(Rect::new(2.0, 0.0, 4.0, 2.0)));
+        assert_eq!(source.custom_size, Some(Vec2::splat(2.0)));
+        assert_eq!(app.world.get::<Sprite>(sprite).unwrap().rect, None);
+        assert_eq!(
+            app.world.get::<Sprite>(sprite).unwrap().custom_size,
+            rotated_size
+        );
+
+        // A new custom size is grown like the first one was
+        app.world.get_mut::<Sprite>(sprite).unwrap().custom_size = Some(Vec2::splat(4.0));
+        app.update();
+        let source = app.world.get::<PixelRotationSource>(sprite).unwrap();
+        assert_eq!(source.custom_size, Some(Vec2::splat(4.0)));
+        assert_eq!(
+            app.world.get::<Sprite>(sprite).unwrap().custom_size,
+            rotated_size.map(|size| size * 2.0)
+        );
+
+        app.world.entity_mut(sprite).remove::<PixelRotation>();
+        app.update();
+        let restored = app.world.get::<Sprite>(sprite).unwrap();
+        assert_eq!(restored.rect, Some(Rect::new(2.0, 0.0, 4.0, 2.0)));
+        assert_eq!(restored.custom_size, Some(Vec2::splat(4.0)));
+        assert_eq!(app.world.get::<Handle<Image>>(sprite), Some(&sheet));
+    }
+
+    #[test]
+    fn rotated_copies_are_freed_with_their_sprites() {
+        let mut app = app();
+        let sheet = app.world.resource_mut::<Assets<Image>>().add(sheet());
+        let sprites = [0, 1].map(|_| {
+            app.world
+                .spawn((
+                    PixelRotation::with_steps(TAU / 4.0, 4),
+                    Sprite::default(),
+                    sheet.clone(),
+                ))
+                .id()
+        });
+        app.update();
+        let rotated = sprites.map(|sprite| app.world.get::<Handle<Image>>(sprite).unwrap().id());
+        assert_eq!(rotated[0], rotated[1]);
+        assert_eq!(app.world.resource::<PixelRotationCache>().images.len(), 1);
+
+        for sprite in sprites {
+            app.world.despawn(sprite);
+        }
+        for _ in 0..6 {
+            app.update();
+        }
+        assert!(!app
+            .world
+            .resource::<Assets<Image>>()
+            .contains(&Handle::weak(rotated[0])));
+        assert!(app.world.resource::<PixelRotationCache>().images.is_empty());
+    }
+}
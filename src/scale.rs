@@ -0,0 +1,309 @@
+use bevy::prelude::*;
+
+/// Reads a pixel, clamping positions outside of the buffer to the closest edge. Empty buffers read as transparent
+fn pixel_at(pixels: &[[u8; 4]], width: usize, height: usize, x: isize, y: isize) -> [u8; 4] {
+    if width == 0 || height == 0 {
+        return [0; 4];
+    }
+    let x = x.clamp(0, width as isize - 1) as usize;
+    let y = y.clamp(0, height as isize - 1) as usize;
+    pixels[y * width + x]
+}
+
+/// Samples the pixel under the center of every output pixel. An empty input gives a transparent output
+pub fn nearest(
+    pixels: &[[u8; 4]],
+    width: usize,
+    height: usize,
+    out_width: usize,
+    out_height: usize,
+) -> Vec<[u8; 4]> {
+    (0..out_height)
+        .flat_map(|y| (0..out_width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let sx = (x as f32 + 0.5) / out_width as f32 * width as f32;
+            let sy = (y as f32 + 0.5) / out_height as f32 * height as f32;
+            pixel_at(pixels, width, height, sx as isize, sy as isize)
+        })
+        .collect()
+}
+
+/// Scales pixels up two times with Scale2x(also known as EPX)
+pub fn scale2x(pixels: &[[u8; 4]], width: usize, height: usize) -> Vec<[u8; 4]> {
+    let mut scaled = vec![[0; 4]; width * 2 * height * 2];
+    for y in 0..height {
+        for x in 0..width {
+            let at = |dx: isize, dy: isize| {
+                pixel_at(pixels, width, height, x as isize + dx, y as isize + dy)
+            };
+            let (b, d, e, f, h) = (at(0, -1), at(-1, 0), at(0, 0), at(1, 0), at(0, 1));
+            let block = if b != h && d != f {
+                [
+                    if d == b { d } else { e },
+                    if b == f { f } else { e },
+                    if d == h { d } else { e },
+                    if h == f { f } else { e },
+                ]
+            } else {
+                [e; 4]
+            };
+            for (index, pixel) in block.into_iter().enumerate() {
+                let (sx, sy) = (x * 2 + index % 2, y * 2 + index / 2);
+                scaled[sy * width * 2 + sx] = pixel;
+            }
+        }
+    }
+    scaled
+}
+
+/// Scales pixels up three times with Scale3x, which fills in diagonal edges instead of making blocks
+pub fn scale3x(pixels: &[[u8; 4]], width: usize, height: usize) -> Vec<[u8; 4]> {
+    let mut scaled = vec![[0; 4]; width * 3 * height * 3];
+    for y in 0..height {
+        for x in 0..width {
+            let at = |dx: isize, dy: isize| {
+                pixel_at(pixels, width, height, x as isize + dx, y as isize + dy)
+            };
+            let (a, b, c) = (at(-1, -1), at(0, -1), at(1, -1));
+            let (d, e, f) = (at(-1, 0), at(0, 0), at(1, 0));
+            let (g, h, i) = (at(-1, 1), at(0, 1), at(1, 1));
+            let block = if b != h && d != f {
+                [
+                    if d == b { d } else { e },
+                    if (d == b && e != c) || (b == f && e != a) {
+                        b
+                    } else {
+                        e
+                    },
+                    if b == f { f } else { e },
+                    if (d == b && e != g) || (d == h && e != a) {
+                        d
+                    } else {
+                        e
+                    },
+                    e,
+                    if (b == f && e != i) || (h == f && e != c) {
+                        f
+                    } else {
+                        e
+                    },
+                    if d == h { d } else { e },
+                    if (d == h && e != i) || (h == f && e != g) {
+                        h
+                    } else {
+                        e
+                    },
+                    if h == f { f } else { e },
+                ]
+            } else {
+                [e; 9]
+            };
+            for (index, pixel) in block.into_iter().enumerate() {
+                let (sx, sy) = (x * 3 + index % 3, y * 3 + index / 3);
+                scaled[sy * width * 3 + sx] = pixel;
+            }
+        }
+    }
+    scaled
+}
+
+/// An 8 bit srgb color in linear space, which is what the shader sees
+fn linear(pixel: [u8; 4]) -> Vec4 {
+    Vec4::from(Color::rgba_u8(pixel[0], pixel[1], pixel[2], pixel[3]).as_linear_rgba_f32())
+}
+
+/// How different two colors look, weighing brightness more than hue like xBR does
+fn yuv_distance(a: [u8; 4], b: [u8; 4]) -> f32 {
+    let difference = (linear(a) - linear(b)).truncate();
+    let y = difference.dot(Vec3::new(0.299, 0.587, 0.114));
+    let u = difference.dot(Vec3::new(-0.169, -0.331, 0.5));
+    let v = difference.dot(Vec3::new(0.5, -0.419, -0.081));
+    48.0 * y.abs() + 7.0 * u.abs() + 6.0 * v.abs()
+}
+
+/// Picks the color of one corner of the 2x2 block of an xBR pixel. `at` reads neighbors already mirrored so the
+/// corner is always the bottom right one
+fn xbr_corner(at: impl Fn(isize, isize) -> [u8; 4]) -> [u8; 4] {
+    let (e, f, h, i) = (at(0, 0), at(1, 0), at(0, 1), at(1, 1));
+    let anti_diagonal = yuv_distance(e, at(1, -1))
+        + yuv_distance(e, at(-1, 1))
+        + yuv_distance(i, at(2, 0))
+        + yuv_distance(i, at(0, 2))
+        + 4.0 * yuv_distance(h, f);
+    let diagonal = yuv_distance(h, at(-1, 0))
+        + yuv_distance(h, at(1, 2))
+        + yuv_distance(f, at(0, -1))
+        + yuv_distance(f, at(2, 1))
+        + 4.0 * yuv_distance(e, i);
+    if anti_diagonal < diagonal && e != f && e != h {
+        if yuv_distance(e, f) <= yuv_distance(e, h) {
+            f
+        } else {
+            h
+        }
+    } else {
+        e
+    }
+}
+
+/// Scales pixels up two times with a level 1 xBR without blending. Edges are found by comparing color differences
+/// along both diagonals so it follows shallower slopes than Scale2x
+pub fn xbr2x(pixels: &[[u8; 4]], width: usize, height: usize) -> Vec<[u8; 4]> {
+    let mut scaled = vec![[0; 4]; width * 2 * height * 2];
+    for y in 0..height {
+        for x in 0..width {
+            for (sx, sy) in [(-1, -1), (1, -1), (-1, 1), (1, 1)] {
+                let corner = xbr_corner(|dx, dy| {
+                    pixel_at(
+                        pixels,
+                        width,
+                        height,
+                        x as isize + dx * sx,
+                        y as isize + dy * sy,
+                    )
+                });
+                let (cx, cy) = (x * 2 + (sx + 1) as usize / 2, y * 2 + (sy + 1) as usize / 2);
+                scaled[cy * width * 2 + cx] = corner;
+            }
+        }
+    }
+    scaled
+}
+
+/// Scales up by the whole number part of the scale with nearest and the rest with bilinear, so pixels stay sharp but
+/// are all the same size. Blending happens in linear space like on the gpu. An empty input gives a transparent output
+pub fn sharp_bilinear(
+    pixels: &[[u8; 4]],
+    width: usize,
+    height: usize,
+    out_width: usize,
+    out_height: usize,
+) -> Vec<[u8; 4]> {
+    if width == 0 || height == 0 {
+        return vec![[0; 4]; out_width * out_height];
+    }
+    let size = Vec2::new(width as f32, height as f32);
+    let scale = Vec2::new(out_width as f32, out_height as f32) / size;
+    let prescale = scale.floor().max(Vec2::ONE);
+    let region = 0.5 - 0.5 / prescale;
+    (0..out_height)
+        .flat_map(|y| (0..out_width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let uv = (Vec2::new(x as f32, y as f32) + 0.5)
+                / Vec2::new(out_width as f32, out_height as f32);
+            let texel = uv * size;
+            let center = texel.fract() - 0.5;
+            let offset = (center - center.clamp(-region, region)) * prescale + 0.5;
+            let position = texel.floor() + offset - 0.5;
+            let base = position.floor();
+            let t = position - base;
+            let at = |dx: f32, dy: f32| {
+                linear(pixel_at(
+                    pixels,
+                    width,
+                    height,
+                    (base.x + dx) as isize,
+                    (base.y + dy) as isize,
+                ))
+            };
+            let top = at(0.0, 0.0).lerp(at(1.0, 0.0), t.x);
+            let bottom = at(0.0, 1.0).lerp(at(1.0, 1.0), t.x);
+            let [r, g, b, a] = top.lerp(bottom, t.y).to_array();
+            let [r, g, b, _] = Color::rgba_linear(r, g, b, a).as_rgba_f32();
+            [r, g, b, a].map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::camera::upscale::UpscaleFilter;
+
+    use super::*;
+
+    const W: [u8; 4] = [255, 255, 255, 255];
+    const K: [u8; 4] = [0, 0, 0, 255];
+
+    /// A black triangle in the top left corner, so the center pixel sits on a diagonal edge
+    const DIAGONAL: [[u8; 4]; 9] = [K, K, W, K, W, W, W, W, W];
+
+    #[test]
+    fn nearest_repeats_pixels() {
+        assert_eq!(nearest(&[W, K], 2, 1, 4, 2), vec![W, W, K, K, W, W, K, K]);
+    }
+
+    #[test]
+    fn scale2x_rounds_diagonal_edges() {
+        let scaled = scale2x(&DIAGONAL, 3, 3);
+        // The block of the center pixel gets the black corner facing the edge and nothing else
+        assert_eq!(scaled[2 * 6 + 2], K);
+        assert_eq!(
+            [scaled[2 * 6 + 3], scaled[3 * 6 + 2], scaled[3 * 6 + 3]],
+            [W; 3]
+        );
+        assert_eq!(scale2x(&[W; 4], 2, 2), vec![W; 16]);
+    }
+
+    #[test]
+    fn scale3x_fills_only_the_corner() {
+        let scaled = scale3x(&DIAGONAL, 3, 3);
+        let block: Vec<_> = (3..6)
+            .flat_map(|y| (3..6).map(move |x| (x, y)))
+            .map(|(x, y)| scaled[y * 9 + x])
+            .collect();
+        assert_eq!(block, vec![K, W, W, W, W, W, W, W, W]);
+
+        // A lone pixel has no edge to follow so it stays a block
+        let mut dot = [W; 9];
+        dot[4] = K;
+        let scaled = scale3x(&dot, 3, 3);
+        for (index, pixel) in scaled.into_iter().enumerate() {
+            let (x, y) = (index % 9, index / 9);
+            let inside = (3..6).contains(&x) && (3..6).contains(&y);
+            assert_eq!(pixel, if inside { K } else { W });
+        }
+    }
+
+    #[test]
+    fn xbr_keeps_the_palette_and_flat_areas() {
+        let scaled = xbr2x(&DIAGONAL, 3, 3);
+        assert_eq!(scaled[2 * 6 + 2], K);
+        assert!(scaled.iter().all(|pixel| *pixel == W || *pixel == K));
+        assert_eq!(xbr2x(&[W; 4], 2, 2), vec![W; 16]);
+    }
+
+    #[test]
+    fn sharp_bilinear_only_blends_seams() {
+        let checker = [W, K, K, W];
+        assert_eq!(
+            sharp_bilinear(&checker, 2, 2, 4, 4),
+            nearest(&checker, 2, 2, 4, 4)
+        );
+
+        // At 2.5x the pixels are drawn 2x and the half pixel left over is the only blend
+        let scaled = sharp_bilinear(&[W, K], 2, 1, 5, 1);
+        assert_eq!([scaled[0], scaled[1], scaled[3], scaled[4]], [W, W, K, K]);
+        assert!(scaled[2] != W && scaled[2] != K);
+        assert_eq!(scaled[2][0], scaled[2][1]);
+    }
+
+    #[test]
+    fn empty_input_gives_empty_or_transparent_output() {
+        assert_eq!(nearest(&[], 0, 0, 2, 3), vec![[0; 4]; 6]);
+        assert_eq!(nearest(&[], 4, 0, 2, 1), vec![[0; 4]; 2]);
+        assert_eq!(sharp_bilinear(&[], 0, 0, 2, 3), vec![[0; 4]; 6]);
+        assert_eq!(sharp_bilinear(&[], 0, 4, 1, 1), vec![[0; 4]]);
+        assert!(scale2x(&[], 0, 0).is_empty());
+        assert!(scale3x(&[], 3, 0).is_empty());
+        assert!(xbr2x(&[], 0, 3).is_empty());
+        for filter in [
+            UpscaleFilter::Nearest,
+            UpscaleFilter::Scale2x,
+            UpscaleFilter::Scale3x,
+            UpscaleFilter::SharpBilinear,
+            UpscaleFilter::Xbr,
+        ] {
+            assert_eq!(filter.apply(&[], 0, 0, 2, 2), vec![[0; 4]; 4]);
+        }
+    }
+}